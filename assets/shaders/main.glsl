@@ -1,158 +1,74 @@
 #version 450
 
-#include "utils.glsl"
-#include "sdf.glsl"
-
-// output image
-layout(set=0, binding=0, rgba8)
-writeonly uniform image2D u_output;
-
-// uniforms
-layout(set=0, binding=1)
-uniform Uniforms {
-	vec3 u_eye;
-	float u_fov;
-	vec3 u_target;
-	float u_time;
+// bindings, scene and sphere tracing shared with pathtrace.glsl
+#include "raymarch.glsl"
+
+// tweakable parameters, the ui builds a widget for each member from its annotation
+layout(set=0, binding=6)
+uniform Params {
+	float u_ambient;   // @slider(0, 2) @default(1)
+	float u_specular;  // @slider(0, 2) @default(1)
+	vec3 u_light_tint; // @color
 };
 
-// constants
-const float MAX_STEPS = 256;
-const float MIN_HIT_DIST = 0.001;
-const float MAX_DIST = 100.0;
-
-#define AA 4
-#define BACKGROUND_ENABLE 1
-#define SHADOW_ENABLED 1
-
-const Material[] materials = {
-	// background materials
-	Material(
-		vec3(1.0, 0.0, 0.0),
-		vec3(1.0, 0.0, 0.0),
-		vec3(0.0),
-		1.0
-	),
-	Material(
-		vec3(0.0, 1.0, 0.0),
-		vec3(0.0, 1.0, 0.0),
-		vec3(0.0),
-		1.0
-	),
-	Material(
-		vec3(0.0, 0.0, 1.0),
-		vec3(0.0, 0.0, 1.0),
-		vec3(0.0),
-		1.0
-	),
-
-	// main scene materials
-	Material(
-		vec3(0.8, 0.1, 0.08),
-		vec3(0.45, 0.02, 0.05),
-		vec3(0.05, 0.05, 0.05),
-		12.0
-	),
-	Material(
-		vec3(0.8, 0.7, 0.5),
-		vec3(0.2, 0.3, 0.4),
-		vec3(0.0),
-		1.0
-	)
-};
-
-Hit background_map(vec3 p) {
-	float bar_length = MAX_DIST;
-	Hit x_axis = Hit(sdInfiniteCylinder(p, vec3(0.0), vec3(1.0, 0.0, 0.0), 0.03), 0);
-	Hit y_axis = Hit(sdInfiniteCylinder(p, vec3(0.0), vec3(0.0, 1.0, 0.0), 0.03), 1);
-	Hit z_axis = Hit(sdInfiniteCylinder(p, vec3(0.0), vec3(0.0, 0.0, 1.0), 0.03), 2);
-
-	return opUnion(opUnion(x_axis, y_axis), z_axis);
-}
-
-Hit scene(vec3 p) {
-	Hit sphere1 = Hit(sdSphere(p, vec3(0.0, 1.1, 0.0), 1.0), 3);
-	Hit box1 = Hit(sdBox(p, vec3(10.0, 0.1, 10.0)), 4);
-	return opUnion(sphere1, box1);
-}
-
-Hit map(vec3 p) {
-#if BACKGROUND_ENABLE
-	return opUnion(scene(p), background_map(p));
-#else
-	return scene(p);
-#endif
-}
-
-vec3 get_normal(vec3 p) {
-	// small step
-	const vec3 h = vec3(MIN_HIT_DIST, 0.0, 0.0);
-
-	// compute gradient coordinates
-	float fp = map(p).dist;
-	float gx = map(p + h.xyy).dist - fp;
-	float gy = map(p + h.yxy).dist - fp;
-	float gz = map(p + h.yyx).dist - fp;
-
-	// normalize gradient to get the normal
-	return normalize(vec3(gx, gy, gz));
-}
-
-Hit ray_cast(vec3 ro, vec3 rd) {
-	Hit t = Hit(0.001, 0);
-	for (int i = 0; i < MAX_STEPS; i++) {
-		Hit d = map(ro + rd * t.dist);
-		if (d.dist <= MIN_HIT_DIST || t.dist >= MAX_DIST) {
-			break;
-		}
-		t.dist += d.dist;
-		t.id = d.id;
-	}
-
-	return t;
-}
-
-// adapt from soft shadows (https://iquilezles.org/www/articles/rmshadows/rmshadows.htm)
-float shadow(vec3 ro, vec3 rd, float tmax, float k) {
-	float res = 1.0;
-	for (float t = MIN_HIT_DIST; t < tmax;) {
-		float h = scene(ro + rd*t).dist;
-		if (h<MIN_HIT_DIST) {
-			return 0.0;
-		}
-		res = min(res, k*h/t);
-		t += h;
-	}
-
-	return res;
-}
-
-vec3 compute_lighting(vec3 ro, vec3 rd, vec3 pos, vec3 normal, vec3 light_pos, int mat_id) {
+// diffuse and specular contribution of a single light
+vec3 compute_lighting(vec3 ro, vec3 rd, vec3 pos, vec3 normal, Light light, int mat_id) {
 	Material mat = materials[mat_id];
 
-	vec3 light_dir = normalize(light_pos - pos);
+	vec3 light_dir;
+	float light_dist;
+	float intensity = light_incidence(light, pos, light_dir, light_dist);
+
 	vec3 view_dir = normalize(ro - pos);
 	vec3 reflect_dir = reflect(-light_dir, normal);
 
 	float dif = max(dot(normal, light_dir), 0.0);
-	float amb = 0.5 + 0.4*dot(normal, vec3(0.0, 1.0, 0.0));
 	float spec = pow(max(dot(view_dir, reflect_dir), 0.0), mat.specular_exponent);
 
 	// shadows
 #if SHADOW_ENABLED
-	if (dif > 0.001) {
-		dif *= shadow(pos + normal * 0.001, light_dir, length(light_pos - pos), 32.0);
+	if (dif > 0.001 && intensity > 0.0) {
+		dif *= shadow(pos + normal * 0.001, light_dir, light_dist, light.shadow_k);
 	}
 #endif
 
-	return mat.diffuse * dif + mat.ambient * amb + mat.specular * spec;
+	return light.color * u_light_tint * intensity * (mat.diffuse * dif + mat.specular * spec * u_specular);
 }
 
-vec3 background_color(vec3 ro, vec3 rd) {
-	return vec3(0.0);
+// views of the sphere tracing replacing the shading, selected by u_shading_mode
+vec3 debug_color(vec3 ro, vec3 rd) {
+	int steps;
+	Hit t = ray_cast(ro, rd, steps);
+	bool missed = t.dist >= MAX_DIST;
+	vec3 pos = ro + t.dist * rd;
+
+	switch (u_shading_mode) {
+	case SHADING_STEPS:
+		return heatmap(float(steps) / MAX_STEPS);
+	case SHADING_DEPTH:
+		return vec3(1.0 - min(t.dist, MAX_DIST) / MAX_DIST);
+	case SHADING_NORMALS:
+		return missed ? vec3(0.0) : get_normal(pos) * 0.5 + 0.5;
+	case SHADING_MATERIAL_ID: {
+		uint h = pcg_hash(uint(t.id));
+		return missed ? vec3(0.0) : vec3(h & 255u, (h >> 8) & 255u, (h >> 16) & 255u) / 255.0;
+	}
+	case SHADING_MASK:
+		// black when missed, red when out of steps, white on a hit
+		if (steps >= MAX_STEPS) {
+			return vec3(1.0, 0.0, 0.0);
+		}
+		return missed ? vec3(0.0) : vec3(1.0);
+	}
+
+	return vec3(1.0, 0.0, 1.0);
 }
 
 vec3 compute_color(vec3 ro, vec3 rd) {
+	if (u_shading_mode != SHADING_LIT) {
+		return debug_color(ro, rd);
+	}
+
 	Hit t = ray_cast(ro, rd);
 
 	if (t.dist >= MAX_DIST) {
@@ -161,9 +77,14 @@ vec3 compute_color(vec3 ro, vec3 rd) {
 
 	vec3 pos = ro + t.dist * rd;
 	vec3 normal = get_normal(pos);
-	vec3 light_pos = vec3(5.0 * sin(u_time * 0.5), 5.0, 5.0 * cos(u_time * 0.5));
 
-	return compute_lighting(ro, rd, pos, normal, light_pos, t.id);
+	float amb = 0.5 + 0.4*dot(normal, vec3(0.0, 1.0, 0.0));
+	vec3 color = materials[t.id].ambient * amb * u_ambient;
+	for (int i = 0; i < light_count; i++) {
+		color += compute_lighting(ro, rd, pos, normal, lights[i], t.id);
+	}
+
+	return color;
 }
 
 layout(local_size_x = 16, local_size_y = 16) in;
@@ -171,9 +92,22 @@ void main() {
 	// translate coordinates from pixel
 	ivec2 coords = ivec2(gl_GlobalInvocationID.xy);
 	ivec2 resolution = imageSize(u_output);
+	// the dispatch is rounded up to whole workgroups
+	if (any(greaterThanEqual(coords, resolution))) {
+		return;
+	}
+
 	vec2 uv = map_pixel_to_screen(coords, resolution);
 	mat3 camera = build_camera(u_eye, u_target);
 
+	// progressive accumulation, a single jittered sample per frame
+	if (u_accumulate != 0) {
+		uint seed = pixel_seed(coords, resolution);
+		vec3 rd = jittered_ray_dir(camera, uv, resolution, seed);
+		accumulate_sample(coords, compute_color(u_eye, rd));
+		return;
+	}
+
 // enable antialiasing
 # if AA>1
 	vec3 final_color = vec3(0.0);
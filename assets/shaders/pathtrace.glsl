@@ -0,0 +1,135 @@
+#version 450
+
+// Progressive path tracer of the same scene as main.glsl. Every dispatch traces one path
+// per pixel and adds it to the accumulated average.
+
+#include "raymarch.glsl"
+
+#define PI 3.14159265359
+
+float luminance(vec3 c) {
+	return dot(c, vec3(0.2126, 0.7152, 0.0722));
+}
+
+// orthonormal basis around n
+mat3 tangent_frame(vec3 n) {
+	vec3 up = abs(n.y) < 0.999 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+	vec3 t = normalize(cross(up, n));
+	return mat3(t, cross(n, t), n);
+}
+
+// cosine weighted direction of the hemisphere around n
+vec3 sample_diffuse(vec3 n, inout uint seed) {
+	float r1 = random(seed);
+	float r2 = random(seed);
+	float phi = 2.0 * PI * r1;
+	float r = sqrt(r2);
+
+	return tangent_frame(n) * vec3(r * cos(phi), r * sin(phi), sqrt(1.0 - r2));
+}
+
+// direction of the phong lobe of the given exponent around the reflected direction
+vec3 sample_glossy(vec3 reflected, float exponent, inout uint seed) {
+	float r1 = random(seed);
+	float r2 = random(seed);
+	float phi = 2.0 * PI * r1;
+	float cos_theta = pow(r2, 1.0 / (exponent + 1.0));
+	float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+	return tangent_frame(reflected) * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+// the lights are points, paths can't hit them so they are sampled at every vertex. Like
+// in compute_lighting the intensity is not attenuated by the distance.
+vec3 direct_lighting(vec3 rd, vec3 pos, vec3 normal, Material mat) {
+	vec3 color = vec3(0.0);
+	for (int i = 0; i < light_count; i++) {
+		vec3 light_dir;
+		float light_dist;
+		float intensity = light_incidence(lights[i], pos, light_dir, light_dist);
+
+		float dif = dot(normal, light_dir);
+		if (dif <= 0.0 || intensity <= 0.0) {
+			continue;
+		}
+
+		// hard shadows, the penumbra comes from the accumulated samples
+		Hit occluder = ray_cast(pos + normal * 2.0 * MIN_HIT_DIST, light_dir);
+		if (occluder.dist < light_dist && occluder.dist < MAX_DIST) {
+			continue;
+		}
+
+		float spec = pow(max(dot(reflect(rd, normal), light_dir), 0.0), mat.specular_exponent);
+		color += lights[i].color * intensity * (mat.diffuse * dif + mat.specular * spec);
+	}
+
+	return color;
+}
+
+vec3 trace_path(vec3 ro, vec3 rd, inout uint seed) {
+	vec3 radiance = vec3(0.0);
+	vec3 throughput = vec3(1.0);
+
+	for (int bounce = 0; bounce <= u_max_bounces; bounce++) {
+		Hit t = ray_cast(ro, rd);
+		if (t.dist >= MAX_DIST) {
+			radiance += throughput * background_color(ro, rd);
+			break;
+		}
+
+		vec3 pos = ro + t.dist * rd;
+		vec3 normal = get_normal(pos);
+		Material mat = materials[t.id];
+
+		radiance += throughput * (mat.emission + direct_lighting(rd, pos, normal, mat));
+
+		// pick the glossy or the diffuse lobe according to their weight
+		float glossy_weight = luminance(mat.specular);
+		float diffuse_weight = luminance(mat.diffuse);
+		float total_weight = glossy_weight + diffuse_weight;
+		if (total_weight <= 0.0) {
+			break;
+		}
+
+		float p_glossy = glossy_weight / total_weight;
+		ro = pos + normal * 2.0 * MIN_HIT_DIST;
+		if (random(seed) < p_glossy) {
+			rd = sample_glossy(reflect(rd, normal), mat.specular_exponent, seed);
+			if (dot(rd, normal) <= 0.0) {
+				break;
+			}
+			throughput *= mat.specular / p_glossy;
+		} else {
+			rd = sample_diffuse(normal, seed);
+			throughput *= mat.diffuse / (1.0 - p_glossy);
+		}
+
+		// russian roulette on the long paths
+		if (bounce >= 3) {
+			float p_continue = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 1.0);
+			if (random(seed) > p_continue) {
+				break;
+			}
+			throughput /= p_continue;
+		}
+	}
+
+	return radiance;
+}
+
+layout(local_size_x = 16, local_size_y = 16) in;
+void main() {
+	ivec2 coords = ivec2(gl_GlobalInvocationID.xy);
+	ivec2 resolution = imageSize(u_output);
+	// the dispatch is rounded up to whole workgroups
+	if (any(greaterThanEqual(coords, resolution))) {
+		return;
+	}
+
+	vec2 uv = map_pixel_to_screen(coords, resolution);
+	mat3 camera = build_camera(u_eye, u_target);
+
+	uint seed = pixel_seed(coords, resolution);
+	vec3 rd = jittered_ray_dir(camera, uv, resolution, seed);
+	accumulate_sample(coords, trace_path(u_eye, rd, seed));
+}
@@ -0,0 +1,182 @@
+// Bindings and sphere tracing functions shared by the compute shaders.
+
+#extension GL_EXT_samplerless_texture_functions : require
+
+#include "utils.glsl"
+#include "sdf.glsl"
+
+// output image
+layout(set=0, binding=0, rgba8)
+writeonly uniform image2D u_output;
+
+// uniforms
+layout(set=0, binding=1)
+uniform Uniforms {
+	vec3 u_eye;
+	float u_fov;
+	vec3 u_target;
+	float u_time;
+	int u_sample_index;
+	int u_accumulate;
+	int u_max_bounces;
+	int u_shading_mode;
+};
+
+// material table, indexed by Hit.id
+layout(set=0, binding=2, std430)
+readonly buffer Materials {
+	Material materials[];
+};
+
+layout(set=0, binding=3, std430)
+readonly buffer Lights {
+	int light_count;
+	Light lights[];
+};
+
+// running average of the accumulated samples, read from the previous frame and written
+// to the other texture
+layout(set=0, binding=4)
+uniform texture2D u_accumulation_in;
+layout(set=0, binding=5, rgba32f)
+writeonly uniform image2D u_accumulation_out;
+
+// settings, the application defines them when they are changed from the ui
+#ifndef MAX_STEPS
+#define MAX_STEPS 256
+#endif
+#ifndef MIN_HIT_DIST
+#define MIN_HIT_DIST 0.001
+#endif
+#ifndef MAX_DIST
+#define MAX_DIST 100.0
+#endif
+
+#ifndef AA
+#define AA 4
+#endif
+#ifndef BACKGROUND_ENABLE
+#define BACKGROUND_ENABLE 1
+#endif
+#ifndef SHADOW_ENABLED
+#define SHADOW_ENABLED 1
+#endif
+
+// scene() function, generated when a scene file is loaded
+#include "scene.glsl"
+
+// material ids 0 to 2 are reserved for the axes
+Hit background_map(vec3 p) {
+	float bar_length = MAX_DIST;
+	Hit x_axis = Hit(sdInfiniteCylinder(p, vec3(0.0), vec3(1.0, 0.0, 0.0), 0.03), 0);
+	Hit y_axis = Hit(sdInfiniteCylinder(p, vec3(0.0), vec3(0.0, 1.0, 0.0), 0.03), 1);
+	Hit z_axis = Hit(sdInfiniteCylinder(p, vec3(0.0), vec3(0.0, 0.0, 1.0), 0.03), 2);
+
+	return opUnion(opUnion(x_axis, y_axis), z_axis);
+}
+
+Hit map(vec3 p) {
+#if BACKGROUND_ENABLE
+	return opUnion(scene(p), background_map(p));
+#else
+	return scene(p);
+#endif
+}
+
+vec3 get_normal(vec3 p) {
+	// small step
+	const vec3 h = vec3(MIN_HIT_DIST, 0.0, 0.0);
+
+	// compute gradient coordinates
+	float fp = map(p).dist;
+	float gx = map(p + h.xyy).dist - fp;
+	float gy = map(p + h.yxy).dist - fp;
+	float gz = map(p + h.yyx).dist - fp;
+
+	// normalize gradient to get the normal
+	return normalize(vec3(gx, gy, gz));
+}
+
+// steps is the number of iterations, MAX_STEPS when the ray did not converge
+Hit ray_cast(vec3 ro, vec3 rd, out int steps) {
+	Hit t = Hit(0.001, 0);
+	for (steps = 0; steps < MAX_STEPS; steps++) {
+		Hit d = map(ro + rd * t.dist);
+		if (d.dist <= MIN_HIT_DIST || t.dist >= MAX_DIST) {
+			break;
+		}
+		t.dist += d.dist;
+		t.id = d.id;
+	}
+
+	return t;
+}
+
+Hit ray_cast(vec3 ro, vec3 rd) {
+	int steps;
+	return ray_cast(ro, rd, steps);
+}
+
+// adapt from soft shadows (https://iquilezles.org/www/articles/rmshadows/rmshadows.htm)
+float shadow(vec3 ro, vec3 rd, float tmax, float k) {
+	float res = 1.0;
+	for (float t = MIN_HIT_DIST; t < tmax;) {
+		float h = scene(ro + rd*t).dist;
+		if (h<MIN_HIT_DIST) {
+			return 0.0;
+		}
+		res = min(res, k*h/t);
+		t += h;
+	}
+
+	return res;
+}
+
+// direction and distance from pos to the light, returns the intensity reaching pos
+float light_incidence(Light light, vec3 pos, out vec3 light_dir, out float light_dist) {
+	float intensity = light.intensity;
+	if (light.kind == LIGHT_DIRECTIONAL) {
+		light_dir = -normalize(light.direction);
+		light_dist = MAX_DIST;
+	} else {
+		light_dir = light.position - pos;
+		light_dist = length(light_dir);
+		light_dir /= light_dist;
+	}
+
+	if (light.kind == LIGHT_SPOT) {
+		float cos_angle = dot(-light_dir, normalize(light.direction));
+		float cos_outer = cos(light.spot_angle);
+		float cos_inner = cos(light.spot_angle * (1.0 - light.spot_softness));
+		intensity *= smoothstep(cos_outer, cos_inner, cos_angle);
+	}
+
+	return intensity;
+}
+
+vec3 background_color(vec3 ro, vec3 rd) {
+	return vec3(0.0);
+}
+
+// seed of the random numbers of a pixel, different for every accumulated sample
+uint pixel_seed(ivec2 coords, ivec2 resolution) {
+	return pcg_hash(uint(coords.y * resolution.x + coords.x) ^ pcg_hash(uint(u_sample_index)));
+}
+
+// direction of a ray going through a random point of the pixel at uv
+vec3 jittered_ray_dir(mat3 camera, vec2 uv, ivec2 resolution, inout uint seed) {
+	vec2 o = (vec2(random(seed), random(seed)) - 0.5) * 2.0 / resolution.y;
+	return get_ray_dir(camera, u_fov, uv.x + o.x, uv.y + o.y);
+}
+
+// add a sample to the running average of the pixel and write the average to the output
+void accumulate_sample(ivec2 coords, vec3 color) {
+	vec3 average = color;
+	if (u_sample_index > 0) {
+		vec3 previous = texelFetch(u_accumulation_in, coords, 0).rgb;
+		average = mix(previous, color, 1.0 / float(u_sample_index + 1));
+	}
+
+	imageStore(u_accumulation_out, coords, vec4(average, 1.0));
+	imageStore(u_output, coords, vec4(average, 1.0));
+}
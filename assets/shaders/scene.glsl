@@ -0,0 +1,9 @@
+// default scene, replaced by the code generated from a scene file when one is loaded.
+// Its materials and lights are defined by default_material_table() and default_lights()
+// in src/scene.rs
+
+Hit scene(vec3 p) {
+	Hit sphere1 = Hit(sdSphere(p, vec3(0.0, 1.1, 0.0), 1.0), 3);
+	Hit box1 = Hit(sdBox(p, vec3(10.0, 0.1, 10.0)), 4);
+	return opUnion(sphere1, box1);
+}
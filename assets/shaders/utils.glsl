@@ -8,8 +8,32 @@ struct Material {
 	vec3 ambient;
 	vec3 specular;
 	float specular_exponent;
+	vec3 emission;
 };
 
+#define LIGHT_POINT 0
+#define LIGHT_DIRECTIONAL 1
+#define LIGHT_SPOT 2
+
+struct Light {
+	vec3 position;
+	int kind;
+	vec3 direction;
+	float intensity;
+	vec3 color;
+	float shadow_k; // shadow softness
+	float spot_angle; // half angle of the cone
+	float spot_softness; // fraction of the cone fading out
+};
+
+// values of u_shading_mode
+#define SHADING_LIT 0
+#define SHADING_STEPS 1
+#define SHADING_DEPTH 2
+#define SHADING_NORMALS 3
+#define SHADING_MATERIAL_ID 4
+#define SHADING_MASK 5
+
 struct Hit {
 	float dist;
 	int id;
@@ -37,3 +61,22 @@ float checker(vec2 p) {
 	vec2 q = floor(p);
 	return mod(q.x+q.y, 2);
 }
+
+// integer hash from https://www.pcg-random.org/
+uint pcg_hash(uint v) {
+	uint state = v * 747796405u + 2891336453u;
+	uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+	return (word >> 22u) ^ word;
+}
+
+// uniform random number in [0, 1), advancing the seed
+float random(inout uint seed) {
+	seed = pcg_hash(seed);
+	return float(seed) / 4294967296.0;
+}
+
+// blue to red color ramp of x in [0, 1]
+vec3 heatmap(float x) {
+	x = clamp(x, 0.0, 1.0);
+	return clamp(vec3(4.0 * x - 2.0, 2.0 - abs(4.0 * x - 2.0), 2.0 - 4.0 * x), 0.0, 1.0);
+}
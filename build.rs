@@ -1,40 +1,77 @@
 use shaderc;
+use std::env;
+use std::fmt::Write;
 use std::fs;
+use std::path::Path;
 
-const SHADERS: &[(&str, shaderc::ShaderKind)] = &[
-    ("main.glsl", shaderc::ShaderKind::Compute),
-    ("quad.vert", shaderc::ShaderKind::Vertex),
-    ("quad.frag", shaderc::ShaderKind::Fragment),
-];
+const SHADER_DIR: &str = "./assets/shaders";
 
+/// Kind of the shader `name` when it is an entry point, `None` for included files.
+fn shader_kind(name: &str, source: &str) -> Option<shaderc::ShaderKind> {
+    if !source.contains("void main(") {
+        return None;
+    }
+
+    Some(match Path::new(name).extension().and_then(|e| e.to_str()) {
+        Some("vert") => shaderc::ShaderKind::Vertex,
+        Some("frag") => shaderc::ShaderKind::Fragment,
+        _ => shaderc::ShaderKind::Compute,
+    })
+}
+
+// every file of `assets/shaders` is embedded in the executable by `src/assets.rs`, along
+// with the SPIR-V of the entry points, through the tables written in `OUT_DIR`
 fn main() {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let shader_dir = Path::new(SHADER_DIR).canonicalize().unwrap();
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+
     let compiler = shaderc::Compiler::new().unwrap();
     let mut opts = shaderc::CompileOptions::new().unwrap();
 
     opts.set_include_callback(|src, _, _, _| {
         Ok(shaderc::ResolvedInclude {
-            resolved_name: format!("./assets/shaders/{}", src),
-            content: fs::read_to_string(format!("./assets/shaders/{}", src)).unwrap(),
+            resolved_name: format!("{}/{}", SHADER_DIR, src),
+            content: fs::read_to_string(format!("{}/{}", SHADER_DIR, src)).unwrap(),
         })
     });
     opts.set_optimization_level(shaderc::OptimizationLevel::Performance);
 
-    for shader in SHADERS {
-        let shader_path = format!("./assets/shaders/{}", shader.0);
+    let mut names: Vec<String> = fs::read_dir(&shader_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .filter(|entry| entry.file_type().unwrap().is_file())
+        .map(|entry| entry.file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+
+    let mut sources = String::from("&[\n");
+    let mut compiled = String::from("&[\n");
+    for name in &names {
+        let path = shader_dir.join(name);
+        println!("cargo:rerun-if-changed={}", path.display());
+        let source = fs::read_to_string(&path).unwrap();
+        writeln!(sources, "    ({:?}, include_str!({:?})),", name, path).unwrap();
+
+        let kind = match shader_kind(name, &source) {
+            Some(kind) => kind,
+            None => continue,
+        };
         let binary_output = compiler
-            .compile_into_spirv(
-                fs::read_to_string(shader_path).unwrap().as_str(),
-                shader.1,
-                shader.0,
-                "main",
-                Some(&opts),
-            )
+            .compile_into_spirv(&source, kind, name, "main", Some(&opts))
             .unwrap();
-
-        fs::write(
-            format!("./assets/compiled_shaders/{}.spv", shader.0),
-            binary_output.as_binary_u8(),
+        let spirv_path = Path::new(&out_dir).join(format!("{}.spv", name));
+        fs::write(&spirv_path, binary_output.as_binary_u8()).unwrap();
+        writeln!(
+            compiled,
+            "    ({:?}, include_bytes!({:?})),",
+            name, spirv_path
         )
-        .unwrap()
+        .unwrap();
     }
+    sources.push(']');
+    compiled.push(']');
+
+    fs::write(Path::new(&out_dir).join("shader_sources.rs"), sources).unwrap();
+    fs::write(Path::new(&out_dir).join("compiled_shaders.rs"), compiled).unwrap();
 }
@@ -0,0 +1,72 @@
+//! Shaders of the application. By default they are embedded in the executable so it runs
+//! from any directory: the GLSL sources, to compile scenes and variants of the defines,
+//! and the SPIR-V compiled by `build.rs`. An assets directory given by `--assets` or
+//! `RAY_MARCH_ASSETS` replaces them by the files of `<dir>/shaders/`, which are hot
+//! reloaded.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Environment variable naming the assets directory, `--assets` takes precedence.
+pub const ASSETS_ENV: &str = "RAY_MARCH_ASSETS";
+
+// all the files of `assets/shaders`, listed by `build.rs`
+const SOURCES: &[(&str, &str)] = include!(concat!(env!("OUT_DIR"), "/shader_sources.rs"));
+
+// the entry points among them, compiled by `build.rs`
+const COMPILED: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/compiled_shaders.rs"));
+
+static DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Read the shaders from `<dir>/shaders/` instead of the embedded ones. Only the first
+/// call has an effect, it must happen before any shader is loaded.
+pub fn set_dir(dir: Option<PathBuf>) {
+    let _ = DIR.set(dir);
+}
+
+/// The assets directory, `None` when the embedded shaders are used.
+pub fn dir() -> Option<&'static Path> {
+    DIR.get_or_init(|| None).as_deref()
+}
+
+/// Path of the shader `name` in the assets directory, or just its name when embedded.
+pub fn shader_path(name: &str) -> PathBuf {
+    match dir() {
+        Some(dir) => dir.join("shaders").join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+/// Source of the shader at `path`, as given by `shader_path`.
+pub fn read_shader(path: &Path) -> io::Result<Cow<'static, str>> {
+    if dir().is_some() {
+        return fs::read_to_string(path).map(Cow::Owned);
+    }
+
+    SOURCES
+        .iter()
+        .find(|(name, _)| Path::new(name) == path)
+        .map(|(_, source)| Cow::Borrowed(*source))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such embedded shader"))
+}
+
+/// SPIR-V of the shader `name` compiled by `build.rs` from the embedded sources.
+pub fn compiled_shader(name: &str) -> &'static [u8] {
+    COMPILED
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, spirv)| *spirv)
+        .unwrap_or_else(|| panic!("no compiled shader {}", name))
+}
+
+/// Directory where the values of the shader parameters are saved, in the assets
+/// directory or else in the working directory.
+pub fn params_dir() -> PathBuf {
+    match dir() {
+        Some(dir) => dir.join("shader_params"),
+        None => PathBuf::from("./shader_params"),
+    }
+}
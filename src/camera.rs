@@ -1,15 +1,26 @@
 use std::time::Duration;
 use wgpu_sandbox::prelude::winit::event::{
-    ElementState, MouseButton, MouseScrollDelta, WindowEvent,
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
 };
 
 use glam::{
-    f32::{Mat4, Vec3},
-    vec3,
+    f32::{Mat4, Vec2, Vec3},
+    vec2, vec3,
 };
 
+use crate::scene::Scene;
+
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    bytemuck::Pod,
+    bytemuck::Zeroable,
+    serde::Serialize,
+    serde::Deserialize,
+)]
 pub struct Camera {
     pub eye: Vec3,
     pub fov: f32,
@@ -20,6 +31,21 @@ impl Camera {
     pub fn new(eye: Vec3, target: Vec3, fov: f32) -> Self {
         Self { eye, target, fov }
     }
+
+    /// Starting point of view of the viewer and offline renders.
+    pub fn initial() -> Self {
+        Self::new(vec3(5.0, 5.0, 5.0), Vec3::ZERO, 1.5)
+    }
+
+    /// Direction of the ray going through `uv`, in the screen coordinates of
+    /// `map_pixel_to_screen` in `utils.glsl`.
+    pub fn ray_dir(&self, uv: Vec2) -> Vec3 {
+        let w = (self.target - self.eye).normalize();
+        let u = w.cross(Vec3::Y).normalize();
+        let v = u.cross(w);
+
+        (u * uv.x + v * uv.y + w * self.fov).normalize()
+    }
 }
 
 impl Default for Camera {
@@ -32,104 +58,724 @@ impl Default for Camera {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum CameraMode {
+    /// Rotate around `target` and zoom towards it.
+    #[default]
+    Orbit,
+    /// Look around from `eye` and move with WASD/QE, `target` follows at the same
+    /// distance so going back to orbit keeps the view.
+    Fly,
+}
+
+impl CameraMode {
+    pub const ALL: [CameraMode; 2] = [CameraMode::Orbit, CameraMode::Fly];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CameraMode::Orbit => "orbit",
+            CameraMode::Fly => "fly",
+        }
+    }
+}
+
+/// Movement keys held down in fly mode.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+struct FlyKeys {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+    fast: bool,
+    slow: bool,
+}
+
+impl FlyKeys {
+    /// Update the key state, returns false for keys which do not move the camera.
+    fn set(&mut self, key: VirtualKeyCode, pressed: bool) -> bool {
+        let held = match key {
+            VirtualKeyCode::W => &mut self.forward,
+            VirtualKeyCode::S => &mut self.back,
+            VirtualKeyCode::A => &mut self.left,
+            VirtualKeyCode::D => &mut self.right,
+            VirtualKeyCode::E => &mut self.up,
+            VirtualKeyCode::Q => &mut self.down,
+            VirtualKeyCode::LShift | VirtualKeyCode::RShift => &mut self.fast,
+            VirtualKeyCode::LControl | VirtualKeyCode::RControl => &mut self.slow,
+            _ => return false,
+        };
+        *held = pressed;
+        true
+    }
+
+    /// Direction of the movement in the camera frame: right, up and forward.
+    fn direction(&self) -> Vec3 {
+        let axis = |pos: bool, neg: bool| pos as i32 as f32 - neg as i32 as f32;
+        vec3(
+            axis(self.right, self.left),
+            axis(self.up, self.down),
+            axis(self.forward, self.back),
+        )
+    }
+
+    fn speed_factor(&self) -> f32 {
+        match (self.fast, self.slow) {
+            (true, false) => 4.0,
+            (false, true) => 0.25,
+            _ => 1.0,
+        }
+    }
+}
+
+/// What a mouse button does while it is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum MouseAction {
+    None,
+    /// Orbit around the target, or look around in fly mode. A double click sets the
+    /// target to the surface under the cursor.
+    Rotate,
+    /// Move the target and the eye in the view plane.
+    Pan,
+}
+
+impl MouseAction {
+    pub const ALL: [MouseAction; 3] = [MouseAction::None, MouseAction::Rotate, MouseAction::Pan];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MouseAction::None => "none",
+            MouseAction::Rotate => "rotate",
+            MouseAction::Pan => "pan",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ButtonMapping {
+    pub left: MouseAction,
+    pub middle: MouseAction,
+    pub right: MouseAction,
+}
+
+impl ButtonMapping {
+    pub fn action(&self, button: MouseButton) -> MouseAction {
+        match button {
+            MouseButton::Left => self.left,
+            MouseButton::Middle => self.middle,
+            MouseButton::Right => self.right,
+            MouseButton::Other(_) => MouseAction::None,
+        }
+    }
+}
+
+impl Default for ButtonMapping {
+    fn default() -> Self {
+        Self {
+            left: MouseAction::Rotate,
+            middle: MouseAction::Pan,
+            right: MouseAction::Pan,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
 pub struct CameraController {
     pub camera: Camera,
+    mode: CameraMode,
+    /// Units per second of the fly mode, before the shift/ctrl modifiers.
+    pub fly_speed: f32,
+    pub buttons: ButtonMapping,
+    /// Rate at which the orbit rotation and zoom catch up with the input, per second.
+    /// Zero applies the input at once.
+    pub damping: f32,
     screen_size: (f32, f32),
-    is_middle_button_hold: bool,
+    rotating: bool,
+    panning: bool,
     last_cursor: (f64, f64),
-    rotation: (f32, f32),
-    zoom: f32,
+    // orbit input not applied yet: pitch and yaw in radians, pan in pixels and zoom in
+    // wheel lines
+    pending_rotation: (f32, f32),
+    pending_pan: (f32, f32),
+    pending_zoom: f32,
+    // cursor movement in pixels since the last update, for the fly mode
+    look: (f32, f32),
+    keys: FlyKeys,
+    // sum of the frame times, double clicks are timed with it so replays see them too
+    clock: f32,
+    last_click: Option<(f32, (f64, f64))>,
+    pick_request: Option<(f64, f64)>,
+    frame_request: bool,
 }
 
 impl CameraController {
-    const ROTATION_SPEED: f32 = 200.0;
-    const ZOOM_SPEED: f32 = 100.0;
+    /// Radians per screen width of cursor movement in orbit mode, half of it vertically.
+    const ROTATION_SPEED: f32 = std::f32::consts::TAU;
+    /// Distance factor of one wheel line in orbit mode.
+    const ZOOM_FACTOR: f32 = 1.15;
+    const PIXELS_PER_LINE: f32 = 50.0;
+    const MIN_DISTANCE: f32 = 0.1;
+    const DEFAULT_DAMPING: f32 = 15.0;
+    /// Pending input below this is applied at once, so the camera stops changing and the
+    /// accumulation can go on.
+    const PENDING_EPSILON: f32 = 1e-4;
+    const DOUBLE_CLICK_TIME: f32 = 0.3;
+    const DOUBLE_CLICK_DISTANCE: f64 = 4.0;
+    /// Radians per pixel of cursor movement in fly mode.
+    const LOOK_SENSITIVITY: f32 = 0.003;
+    const MAX_PITCH: f32 = 1.55;
+    const DEFAULT_FLY_SPEED: f32 = 2.0;
 
     pub fn new(camera: Camera, screen_size: (f32, f32)) -> Self {
         Self {
             camera,
+            mode: CameraMode::default(),
+            fly_speed: Self::DEFAULT_FLY_SPEED,
+            buttons: ButtonMapping::default(),
+            damping: Self::DEFAULT_DAMPING,
             screen_size,
-            is_middle_button_hold: false,
+            rotating: false,
+            panning: false,
             last_cursor: (0.0, 0.0),
-            rotation: (0.0, 0.0),
-            zoom: 0.0,
+            pending_rotation: (0.0, 0.0),
+            pending_pan: (0.0, 0.0),
+            pending_zoom: 0.0,
+            look: (0.0, 0.0),
+            keys: FlyKeys::default(),
+            clock: 0.0,
+            last_click: None,
+            pick_request: None,
+            frame_request: false,
         }
     }
 
+    pub fn set_screen_size(&mut self, screen_size: (f32, f32)) {
+        self.screen_size = screen_size;
+    }
+
+    pub fn mode(&self) -> CameraMode {
+        self.mode
+    }
+
+    /// Switch mode, the camera is left as is.
+    pub fn set_mode(&mut self, mode: CameraMode) {
+        self.mode = mode;
+        self.keys = FlyKeys::default();
+        self.stop();
+    }
+
+    /// Drop the orbit input which was not applied yet.
+    fn stop(&mut self) {
+        self.pending_rotation = (0.0, 0.0);
+        self.pending_pan = (0.0, 0.0);
+        self.pending_zoom = 0.0;
+    }
+
     pub fn handle_events(&mut self, event: &WindowEvent) {
         match event {
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state: ElementState::Pressed,
-                ..
-            } => self.is_middle_button_hold = true,
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state: ElementState::Released,
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
                 ..
-            } => self.is_middle_button_hold = false,
+            } => {
+                let pressed = *state == ElementState::Pressed;
+                if *key == VirtualKeyCode::Tab && pressed {
+                    self.set_mode(match self.mode {
+                        CameraMode::Orbit => CameraMode::Fly,
+                        CameraMode::Fly => CameraMode::Orbit,
+                    });
+                } else if *key == VirtualKeyCode::F && pressed && self.mode == CameraMode::Orbit {
+                    self.frame_request = true;
+                } else if self.mode == CameraMode::Fly {
+                    self.keys.set(*key, pressed);
+                }
+            }
+            // keys and buttons released while the window is not focused are never reported
+            WindowEvent::Focused(false) => {
+                self.keys = FlyKeys::default();
+                self.rotating = false;
+                self.panning = false;
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let pressed = *state == ElementState::Pressed;
+                match self.buttons.action(*button) {
+                    MouseAction::Rotate => {
+                        self.rotating = pressed;
+                        if pressed {
+                            self.click();
+                        }
+                    }
+                    MouseAction::Pan => self.panning = pressed,
+                    MouseAction::None => (),
+                }
+            }
             WindowEvent::CursorMoved { position, .. } => {
                 let (dx, dy) = (
-                    (self.last_cursor.0 - position.x) as f32 / self.screen_size.0,
-                    (self.last_cursor.1 - position.y) as f32 / self.screen_size.1,
+                    (position.x - self.last_cursor.0) as f32,
+                    (position.y - self.last_cursor.1) as f32,
                 );
-                self.rotation = (-std::f32::consts::FRAC_PI_2 * dy, std::f32::consts::PI * dx);
+                match self.mode {
+                    CameraMode::Orbit if self.rotating => {
+                        self.pending_rotation.0 +=
+                            0.5 * Self::ROTATION_SPEED * dy / self.screen_size.1;
+                        self.pending_rotation.1 -= Self::ROTATION_SPEED * dx / self.screen_size.0;
+                    }
+                    CameraMode::Orbit if self.panning => {
+                        self.pending_pan.0 += dx;
+                        self.pending_pan.1 += dy;
+                    }
+                    CameraMode::Fly if self.rotating => {
+                        self.look.0 += dx;
+                        self.look.1 += dy;
+                    }
+                    _ => (),
+                }
                 self.last_cursor = (position.x, position.y);
             }
-            WindowEvent::MouseWheel { delta, .. } => match delta {
-                MouseScrollDelta::LineDelta(_, dy) => self.zoom = *dy,
-                _ => (),
-            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, dy) => *dy,
+                    MouseScrollDelta::PixelDelta(p) => p.y as f32 / Self::PIXELS_PER_LINE,
+                };
+                match self.mode {
+                    CameraMode::Orbit => self.pending_zoom += lines,
+                    // the wheel changes the speed in fly mode
+                    CameraMode::Fly => {
+                        self.fly_speed = (self.fly_speed * 1.2f32.powf(lines)).clamp(0.01, 1000.0)
+                    }
+                }
+            }
             _ => (),
         }
     }
 
+    /// A second click close to the previous one, in time and space, asks for a pick.
+    fn click(&mut self) {
+        let cursor = self.last_cursor;
+        match self.last_click {
+            Some((time, (x, y)))
+                if self.clock - time < Self::DOUBLE_CLICK_TIME
+                    && (x - cursor.0).hypot(y - cursor.1) < Self::DOUBLE_CLICK_DISTANCE =>
+            {
+                self.pick_request = Some(cursor);
+                self.last_click = None;
+            }
+            _ => self.last_click = Some((self.clock, cursor)),
+        }
+    }
+
     pub fn update(&mut self, dt: Duration) {
-        let dir = (self.camera.target - self.camera.eye).normalize();
+        self.clock += dt.as_secs_f32();
+        match self.mode {
+            CameraMode::Orbit => self.update_orbit(dt),
+            CameraMode::Fly => self.update_fly(dt),
+        }
 
-        if self.is_middle_button_hold {
-            let rotx = Mat4::from_axis_angle(
-                Vec3::Y.cross(dir),
-                self.rotation.0 * Self::ROTATION_SPEED * dt.as_secs_f32(),
-            );
-            let roty =
-                Mat4::from_rotation_y(self.rotation.1 * Self::ROTATION_SPEED * dt.as_secs_f32());
+        self.look = (0.0, 0.0);
+    }
 
-            let eye = rotx.transform_vector3(self.camera.eye);
-            let eye = roty.transform_vector3(eye);
+    /// Run the actions needing the scene, requested by the events since the last call:
+    /// framing the scene bounds and setting the target to the surface under the cursor.
+    pub fn scene_actions(&mut self, scene: &Scene) {
+        if std::mem::take(&mut self.frame_request) {
+            self.frame_scene(scene);
+        }
 
-            if (eye - self.camera.target).normalize().dot(Vec3::Y).abs() < 0.95 {
-                self.camera.eye = eye;
+        if let Some((x, y)) = self.pick_request.take() {
+            let (width, height) = self.screen_size;
+            let uv = vec2(
+                (2.0 * x as f32 - width) / height,
+                -(2.0 * y as f32 - height) / height,
+            );
+            let dir = self.camera.ray_dir(uv);
+            if let Some(dist) = scene.ray_cast(self.camera.eye, dir) {
+                self.set_target(self.camera.eye + dir * dist);
             }
         }
+    }
 
-        if self.zoom != 0.0 {
-            let new_eye = Mat4::from_translation(
-                dir * Self::ZOOM_SPEED * dt.as_secs_f32() * self.zoom as f32,
-            )
-            .transform_point3(self.camera.eye);
+    /// Look at `target` from the current eye, the orbit then turns around it.
+    pub fn set_target(&mut self, target: Vec3) {
+        if (target - self.camera.eye).length() > Self::MIN_DISTANCE {
+            self.camera.target = target;
+            self.stop();
+        }
+    }
 
-            if (new_eye - self.camera.target).length() > 1.0 {
-                self.camera.eye = new_eye;
-            }
+    /// Keep the view direction and move the camera until the scene bounds fill the view.
+    /// Scenes without finite bounds are left alone.
+    pub fn frame_scene(&mut self, scene: &Scene) {
+        let bounds = match scene.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        // the screen height spans 2 / fov at a distance of 1, the narrowest side has to
+        // fit the bounding sphere
+        let aspect = (self.screen_size.0 / self.screen_size.1).min(1.0);
+        let half_angle = (aspect / self.camera.fov).atan();
+        let distance = (bounds.radius() / half_angle.sin()).max(Self::MIN_DISTANCE);
+
+        let dir = (self.camera.target - self.camera.eye).normalize();
+        self.camera.target = bounds.center();
+        self.camera.eye = bounds.center() - dir * distance;
+        self.stop();
+    }
+
+    /// Rotation and zoom move a fraction of the pending input each frame, exponential
+    /// smoothing which does not depend on the frame rate, and the total movement only
+    /// depends on the cursor. Panning follows the cursor at once.
+    fn update_orbit(&mut self, dt: Duration) {
+        let k = if self.damping > 0.0 {
+            1.0 - (-self.damping * dt.as_secs_f32()).exp()
+        } else {
+            1.0
+        };
+        let pitch = consume(&mut self.pending_rotation.0, k);
+        let yaw = consume(&mut self.pending_rotation.1, k);
+        let zoom = consume(&mut self.pending_zoom, k);
+
+        if pitch != 0.0 || yaw != 0.0 {
+            let offset = self.camera.eye - self.camera.target;
+            let dir = -offset.normalize();
+            let rotx = Mat4::from_axis_angle(Vec3::Y.cross(dir).normalize(), pitch);
+            let roty = Mat4::from_rotation_y(yaw);
+
+            // the pitch stops short of the poles, where the camera basis is undefined
+            let pitched = rotx.transform_vector3(offset);
+            let offset = if pitched.normalize().dot(Vec3::Y).abs() < 0.95 {
+                pitched
+            } else {
+                offset
+            };
+            self.camera.eye = self.camera.target + roty.transform_vector3(offset);
+        }
+
+        if zoom != 0.0 {
+            let offset = self.camera.eye - self.camera.target;
+            let distance =
+                (offset.length() * Self::ZOOM_FACTOR.powf(-zoom)).max(Self::MIN_DISTANCE);
+            self.camera.eye = self.camera.target + offset.normalize() * distance;
         }
 
-        // reset properties
-        self.rotation = (0.0, 0.0);
-        self.zoom = 0.0;
+        if self.pending_pan != (0.0, 0.0) {
+            let offset = self.camera.target - self.camera.eye;
+            let distance = offset.length();
+            let w = offset / distance;
+            let u = w.cross(Vec3::Y).normalize();
+            let v = u.cross(w);
+
+            // size of a pixel at the target, the screen height spans 2 / fov at a distance
+            // of 1; the camera moves against the cursor so the scene follows it
+            let pixel = 2.0 * distance / (self.camera.fov * self.screen_size.1);
+            let shift = (v * self.pending_pan.1 - u * self.pending_pan.0) * pixel;
+            self.camera.eye += shift;
+            self.camera.target += shift;
+            self.pending_pan = (0.0, 0.0);
+        }
+    }
+
+    /// Mouse-look is driven by the cursor movement alone and the velocity is scaled by
+    /// `dt`, so both behave the same at any frame rate.
+    fn update_fly(&mut self, dt: Duration) {
+        let movement = self.keys.direction();
+        if self.look == (0.0, 0.0) && movement == Vec3::ZERO {
+            // leave the camera untouched, any change restarts the accumulation
+            return;
+        }
+
+        let offset = self.camera.target - self.camera.eye;
+        let distance = offset.length();
+        let dir = offset / distance;
+        let yaw = dir.x.atan2(dir.z) - self.look.0 * Self::LOOK_SENSITIVITY;
+        let pitch = (dir.y.clamp(-1.0, 1.0).asin() - self.look.1 * Self::LOOK_SENSITIVITY)
+            .clamp(-Self::MAX_PITCH, Self::MAX_PITCH);
+        let forward = vec3(
+            pitch.cos() * yaw.sin(),
+            pitch.sin(),
+            pitch.cos() * yaw.cos(),
+        );
+        let right = forward.cross(Vec3::Y).normalize();
+
+        if movement != Vec3::ZERO {
+            let velocity = (right * movement.x + Vec3::Y * movement.y + forward * movement.z)
+                .normalize()
+                * self.fly_speed
+                * self.keys.speed_factor();
+            self.camera.eye += velocity * dt.as_secs_f32();
+        }
+        self.camera.target = self.camera.eye + forward * distance;
     }
 }
 
 impl Default for CameraController {
     fn default() -> Self {
-        Self {
-            camera: Camera::default(),
-            screen_size: (1280.0, 720.0),
-            rotation: (0.0, 0.0),
-            last_cursor: (0.0, 0.0),
-            zoom: 0.0,
-            is_middle_button_hold: false,
+        Self::new(Camera::default(), (1280.0, 720.0))
+    }
+}
+
+/// Take the fraction `k` of `pending`, or all of it once it is small enough.
+fn consume(pending: &mut f32, k: f32) -> f32 {
+    let step = if pending.abs() < CameraController::PENDING_EPSILON {
+        *pending
+    } else {
+        *pending * k
+    };
+    *pending -= step;
+    step
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu_sandbox::prelude::winit::event::DeviceId;
+
+    #[allow(deprecated)]
+    fn key(key: VirtualKeyCode, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[allow(deprecated)]
+    fn mouse(button: MouseButton, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::MouseInput {
+            device_id: unsafe { DeviceId::dummy() },
+            state,
+            button,
+            modifiers: Default::default(),
+        }
+    }
+
+    #[allow(deprecated)]
+    fn cursor(x: f64, y: f64) -> WindowEvent<'static> {
+        WindowEvent::CursorMoved {
+            device_id: unsafe { DeviceId::dummy() },
+            position: (x, y).into(),
+            modifiers: Default::default(),
         }
     }
+
+    #[allow(deprecated)]
+    fn wheel(lines: f32) -> WindowEvent<'static> {
+        WindowEvent::MouseWheel {
+            device_id: unsafe { DeviceId::dummy() },
+            delta: MouseScrollDelta::LineDelta(0.0, lines),
+            phase: wgpu_sandbox::prelude::winit::event::TouchPhase::Moved,
+            modifiers: Default::default(),
+        }
+    }
+
+    fn fly_controller() -> CameraController {
+        let mut controller = CameraController::new(Camera::initial(), (800.0, 600.0));
+        controller.set_mode(CameraMode::Fly);
+        controller
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn fly_speed_does_not_depend_on_the_frame_rate() {
+        let mut once = fly_controller();
+        let mut split = fly_controller();
+        for controller in [&mut once, &mut split] {
+            controller.handle_events(&key(VirtualKeyCode::W, ElementState::Pressed));
+            controller.handle_events(&key(VirtualKeyCode::D, ElementState::Pressed));
+        }
+
+        once.update(Duration::from_millis(400));
+        for _ in 0..5 {
+            split.update(Duration::from_millis(80));
+        }
+        assert_near(once.camera.eye, split.camera.eye);
+        assert_near(once.camera.target, split.camera.target);
+
+        let moved = (once.camera.eye - Camera::initial().eye).length();
+        assert!((moved - 0.4 * CameraController::DEFAULT_FLY_SPEED).abs() < 1e-4);
+    }
+
+    #[test]
+    fn switching_modes_keeps_the_view() {
+        let initial = Camera::initial();
+        let dir = |c: &Camera| (c.target - c.eye).normalize();
+
+        let mut controller = CameraController::new(initial, (800.0, 600.0));
+        controller.handle_events(&key(VirtualKeyCode::Tab, ElementState::Pressed));
+        assert_eq!(controller.mode(), CameraMode::Fly);
+        controller.update(Duration::from_millis(16));
+        assert_eq!(controller.camera, initial);
+
+        // flying moves the eye along the view, the target keeps its distance
+        controller.handle_events(&key(VirtualKeyCode::W, ElementState::Pressed));
+        controller.update(Duration::from_millis(500));
+        let flown = controller.camera;
+        assert_near(dir(&flown), dir(&initial));
+        assert!(
+            ((flown.target - flown.eye).length() - (initial.target - initial.eye).length()).abs()
+                < 1e-4
+        );
+
+        // the held key is dropped with the mode
+        controller.handle_events(&key(VirtualKeyCode::Tab, ElementState::Pressed));
+        assert_eq!(controller.mode(), CameraMode::Orbit);
+        controller.update(Duration::from_millis(16));
+        assert_eq!(controller.camera, flown);
+        controller.set_mode(CameraMode::Fly);
+        controller.update(Duration::from_millis(16));
+        assert_eq!(controller.camera, flown);
+    }
+
+    #[test]
+    fn damping_does_not_depend_on_the_frame_rate() {
+        let mut once = CameraController::new(Camera::initial(), (800.0, 600.0));
+        let mut split = once;
+        // a horizontal drag only turns around the vertical axis, which commutes
+        for controller in [&mut once, &mut split] {
+            controller.handle_events(&cursor(400.0, 300.0));
+            controller.handle_events(&mouse(MouseButton::Left, ElementState::Pressed));
+            controller.handle_events(&cursor(500.0, 300.0));
+            controller.handle_events(&wheel(2.0));
+        }
+
+        once.update(Duration::from_millis(100));
+        for _ in 0..10 {
+            split.update(Duration::from_millis(10));
+        }
+        assert_near(once.camera.eye, split.camera.eye);
+        assert_ne!(once.camera.eye, Camera::initial().eye);
+
+        // the camera ends where the input asked, whatever the damping
+        let mut undamped = CameraController::new(Camera::initial(), (800.0, 600.0));
+        undamped.damping = 0.0;
+        undamped.handle_events(&cursor(400.0, 300.0));
+        undamped.handle_events(&mouse(MouseButton::Left, ElementState::Pressed));
+        undamped.handle_events(&cursor(500.0, 300.0));
+        undamped.handle_events(&wheel(2.0));
+        undamped.update(Duration::from_millis(10));
+        for _ in 0..100 {
+            once.update(Duration::from_millis(100));
+        }
+        assert_near(once.camera.eye, undamped.camera.eye);
+    }
+
+    #[test]
+    fn pan_follows_the_cursor() {
+        let initial = Camera::initial();
+        let mut controller = CameraController::new(initial, (800.0, 600.0));
+        controller.handle_events(&cursor(400.0, 300.0));
+        controller.handle_events(&mouse(MouseButton::Middle, ElementState::Pressed));
+        controller.handle_events(&cursor(410.0, 280.0));
+        controller.update(Duration::from_millis(16));
+
+        let shift = controller.camera.eye - initial.eye;
+        assert_near(controller.camera.target - initial.target, shift);
+
+        // dragged to the right and up, the camera goes left and down so the scene follows
+        let w = (initial.target - initial.eye).normalize();
+        let u = w.cross(Vec3::Y).normalize();
+        let v = u.cross(w);
+        let pixel = 2.0 * (initial.target - initial.eye).length() / (initial.fov * 600.0);
+        assert_near(shift, (-10.0 * u - 20.0 * v) * pixel);
+    }
+
+    #[test]
+    fn frame_scene_shows_the_bounds() {
+        let scene = Scene::builtin();
+        let bounds = scene.bounds().unwrap();
+        let (width, height) = (800.0, 600.0);
+        let mut controller = CameraController::new(Camera::initial(), (width, height));
+        controller.frame_scene(&scene);
+
+        let camera = controller.camera;
+        assert_near(camera.target, bounds.center());
+        let w = (camera.target - camera.eye).normalize();
+        assert_near(
+            w,
+            (Camera::initial().target - Camera::initial().eye).normalize(),
+        );
+
+        // every corner projects inside the screen, in the coordinates of `ray_dir`
+        let u = w.cross(Vec3::Y).normalize();
+        let v = u.cross(w);
+        for i in 0..8 {
+            let corner = vec3(
+                if i & 1 == 0 {
+                    bounds.min.x
+                } else {
+                    bounds.max.x
+                },
+                if i & 2 == 0 {
+                    bounds.min.y
+                } else {
+                    bounds.max.y
+                },
+                if i & 4 == 0 {
+                    bounds.min.z
+                } else {
+                    bounds.max.z
+                },
+            );
+            let d = corner - camera.eye;
+            let uv = vec2(d.dot(u), d.dot(v)) * camera.fov / d.dot(w);
+            assert!(d.dot(w) > 0.0);
+            assert!(
+                uv.x.abs() <= width / height && uv.y.abs() <= 1.0,
+                "{} {}",
+                corner,
+                uv
+            );
+        }
+    }
+
+    fn double_click(controller: &mut CameraController, x: f64, y: f64) {
+        controller.handle_events(&cursor(x, y));
+        for state in [
+            ElementState::Pressed,
+            ElementState::Released,
+            ElementState::Pressed,
+            ElementState::Released,
+        ] {
+            controller.handle_events(&mouse(MouseButton::Left, state));
+        }
+        controller.update(Duration::from_millis(16));
+    }
+
+    #[test]
+    fn picking() {
+        let scene = Scene::builtin();
+        let camera = Camera::new(vec3(0.0, 1.1, 6.0), vec3(0.0, 1.1, 0.0), 1.5);
+        let mut controller = CameraController::new(camera, (800.0, 600.0));
+
+        // the center of the screen looks at the front of the sphere
+        double_click(&mut controller, 400.0, 300.0);
+        controller.scene_actions(&scene);
+        assert_near(controller.camera.target, vec3(0.0, 1.1, 1.0));
+        assert_eq!(controller.camera.eye, camera.eye);
+
+        // the top of the screen only sees the sky
+        let target = controller.camera.target;
+        double_click(&mut controller, 400.0, 0.0);
+        controller.scene_actions(&scene);
+        assert_eq!(controller.camera.target, target);
+
+        // clicks too far apart are not a double click
+        controller.handle_events(&mouse(MouseButton::Left, ElementState::Pressed));
+        controller.update(Duration::from_millis(500));
+        controller.handle_events(&mouse(MouseButton::Left, ElementState::Pressed));
+        assert!(controller.pick_request.is_none());
+    }
 }
@@ -0,0 +1,419 @@
+//! Keyframed camera animation, played by the viewer and by the export command.
+//!
+//! A track is saved in RON, like scenes.
+
+use std::fs;
+use std::io;
+use std::ops::{Add, Mul, Sub};
+use std::path::Path;
+
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+
+/// Curve going through the keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Interpolation {
+    Linear,
+    /// Uniform Catmull-Rom spline, tangents only depend on the neighbouring keys.
+    #[default]
+    CatmullRom,
+    /// Cubic Bezier segments, the handles are scaled by the time between the keys so the
+    /// speed stays continuous when keys are unevenly spaced.
+    Bezier,
+}
+
+impl Interpolation {
+    pub const ALL: [Interpolation; 3] = [
+        Interpolation::Linear,
+        Interpolation::CatmullRom,
+        Interpolation::Bezier,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Interpolation::Linear => "linear",
+            Interpolation::CatmullRom => "catmull-rom",
+            Interpolation::Bezier => "bezier",
+        }
+    }
+}
+
+/// Timing of the segment starting at a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Ease {
+    #[default]
+    Linear,
+    In,
+    Out,
+    InOut,
+}
+
+impl Ease {
+    pub const ALL: [Ease; 4] = [Ease::Linear, Ease::In, Ease::Out, Ease::InOut];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Ease::Linear => "linear",
+            Ease::In => "ease in",
+            Ease::Out => "ease out",
+            Ease::InOut => "ease in out",
+        }
+    }
+
+    /// Remap `t` in [0, 1], the ends are kept.
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Ease::Linear => t,
+            Ease::In => t * t * t,
+            Ease::Out => 1.0 - (1.0 - t).powi(3),
+            Ease::InOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraKey {
+    pub time: f32,
+    pub camera: Camera,
+    #[serde(default)]
+    pub ease: Ease,
+    // given by the track, stays the same when the key is moved
+    #[serde(skip)]
+    id: u32,
+}
+
+impl CameraKey {
+    pub fn new(time: f32, camera: Camera) -> Self {
+        Self {
+            time,
+            camera,
+            ease: Ease::default(),
+            id: 0,
+        }
+    }
+
+    /// Identifier of the key in its track, unlike its index it does not change when the
+    /// keys are reordered.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+/// Keys sorted by time. Before the first key and after the last one the camera stays on
+/// the closest key.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraTrack {
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    keys: Vec<CameraKey>,
+    #[serde(skip)]
+    next_id: u32,
+}
+
+impl CameraTrack {
+    /// Keys closer than this are considered at the same time.
+    const TIME_EPSILON: f32 = 1e-3;
+
+    pub fn new(interpolation: Interpolation) -> Self {
+        Self {
+            interpolation,
+            keys: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut track: Self = ron::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        for i in 0..track.keys.len() {
+            track.keys[i].id = track.new_id();
+        }
+        track.sort();
+
+        Ok(track)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let ron = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(io::Error::other)?;
+        fs::write(path, ron)
+    }
+
+    pub fn keys(&self) -> &[CameraKey] {
+        &self.keys
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Time of the last key.
+    pub fn end(&self) -> f32 {
+        self.keys.last().map_or(0.0, |k| k.time)
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Add `key`, replacing the one at the same time if any. Returns its index.
+    pub fn insert_key(&mut self, mut key: CameraKey) -> usize {
+        if let Some(i) = self
+            .keys
+            .iter()
+            .position(|k| (k.time - key.time).abs() < Self::TIME_EPSILON)
+        {
+            key.id = self.keys[i].id;
+            self.keys[i] = key;
+            return i;
+        }
+
+        key.id = self.new_id();
+        let i = self.keys.partition_point(|k| k.time < key.time);
+        self.keys.insert(i, key);
+        i
+    }
+
+    pub fn remove_key(&mut self, index: usize) -> CameraKey {
+        self.keys.remove(index)
+    }
+
+    /// Replace the key at `index`, it is moved to keep the keys sorted and keeps its id.
+    /// Returns its new index.
+    pub fn set_key(&mut self, index: usize, mut key: CameraKey) -> usize {
+        key.id = self.keys[index].id;
+        self.keys[index] = key;
+        self.sort();
+        self.keys.iter().position(|k| k.id == key.id).unwrap()
+    }
+
+    fn sort(&mut self) {
+        self.keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// Camera at `time`, `None` for an empty track.
+    pub fn sample(&self, time: f32) -> Option<Camera> {
+        let first = self.keys.first()?;
+        let last = self.keys.last()?;
+        if time <= first.time {
+            return Some(first.camera);
+        }
+        if time >= last.time {
+            return Some(last.camera);
+        }
+
+        // segment from key i to key i + 1
+        let i = self.keys.partition_point(|k| k.time <= time) - 1;
+        let key = |j: isize| self.keys[j.clamp(0, self.keys.len() as isize - 1) as usize];
+        let k = [
+            key(i as isize - 1),
+            key(i as isize),
+            key(i as isize + 1),
+            key(i as isize + 2),
+        ];
+
+        let t = (time - k[1].time) / (k[2].time - k[1].time);
+        let t = k[1].ease.apply(t);
+        let times = [k[0].time, k[1].time, k[2].time, k[3].time];
+        let eye = self.interpolate(times, k.map(|k| k.camera.eye), t);
+        let target = self.interpolate(times, k.map(|k| k.camera.target), t);
+        let fov = self.interpolate(times, k.map(|k| k.camera.fov), t);
+
+        Some(Camera::new(eye, target, fov))
+    }
+
+    /// Value between `p[1]` and `p[2]`, `p[0]` and `p[3]` are the neighbouring keys,
+    /// repeated at the ends of the track.
+    fn interpolate<T: Spline>(&self, times: [f32; 4], p: [T; 4], t: f32) -> T {
+        match self.interpolation {
+            Interpolation::Linear => p[1] + (p[2] - p[1]) * t,
+            Interpolation::CatmullRom => {
+                let m1 = (p[2] - p[0]) * 0.5;
+                let m2 = (p[3] - p[1]) * 0.5;
+                hermite(p[1], m1, p[2], m2, t)
+            }
+            Interpolation::Bezier => {
+                let span = times[2] - times[1];
+                let m1 = velocity(times[0], p[0], times[1], p[1], times[2], p[2]) * span;
+                let m2 = velocity(times[1], p[1], times[2], p[2], times[3], p[3]) * span;
+                bezier(
+                    p[1],
+                    p[1] + m1 * (1.0 / 3.0),
+                    p[2] - m2 * (1.0 / 3.0),
+                    p[2],
+                    t,
+                )
+            }
+        }
+    }
+}
+
+/// Values the camera track can interpolate.
+trait Spline: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {
+    const ZERO: Self;
+}
+
+impl Spline for f32 {
+    const ZERO: Self = 0.0;
+}
+
+impl Spline for Vec3 {
+    const ZERO: Self = Vec3::ZERO;
+}
+
+fn hermite<T: Spline>(p1: T, m1: T, p2: T, m2: T, t: f32) -> T {
+    let (t2, t3) = (t * t, t * t * t);
+    p1 * (2.0 * t3 - 3.0 * t2 + 1.0)
+        + m1 * (t3 - 2.0 * t2 + t)
+        + p2 * (-2.0 * t3 + 3.0 * t2)
+        + m2 * (t3 - t2)
+}
+
+fn bezier<T: Spline>(b0: T, b1: T, b2: T, b3: T, t: f32) -> T {
+    let s = 1.0 - t;
+    b0 * (s * s * s) + b1 * (3.0 * s * s * t) + b2 * (3.0 * s * t * t) + b3 * (t * t * t)
+}
+
+/// Rate of change at the middle key, the average of the slopes of both segments. Keys
+/// repeated at the ends of the track give a one sided slope.
+fn velocity<T: Spline>(t0: f32, p0: T, t1: f32, p1: T, t2: f32, p2: T) -> T {
+    let slope = |ta: f32, pa: T, tb: f32, pb: T| {
+        if tb - ta > 0.0 {
+            Some((pb - pa) * (1.0 / (tb - ta)))
+        } else {
+            None
+        }
+    };
+
+    match (slope(t0, p0, t1, p1), slope(t1, p1, t2, p2)) {
+        (Some(a), Some(b)) => (a + b) * 0.5,
+        (Some(s), None) | (None, Some(s)) => s,
+        (None, None) => T::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::vec3;
+
+    fn camera(x: f32) -> Camera {
+        Camera::new(vec3(x, 1.0, 0.0), Vec3::ZERO, 1.0)
+    }
+
+    fn track(interpolation: Interpolation, times: &[f32]) -> CameraTrack {
+        let mut track = CameraTrack::new(interpolation);
+        for (i, time) in times.iter().enumerate() {
+            track.insert_key(CameraKey::new(*time, camera(i as f32)));
+        }
+        track
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn sample_outside_of_the_keys() {
+        assert_eq!(CameraTrack::default().sample(0.0), None);
+
+        let track = track(Interpolation::CatmullRom, &[1.0, 2.0, 4.0]);
+        assert_eq!(track.sample(-5.0), Some(camera(0.0)));
+        assert_eq!(track.sample(1.0), Some(camera(0.0)));
+        assert_eq!(track.sample(4.0), Some(camera(2.0)));
+        assert_eq!(track.sample(100.0), Some(camera(2.0)));
+    }
+
+    #[test]
+    fn interpolations() {
+        // uneven keys, only the bezier handles depend on the time between them
+        let times = [0.0, 1.0, 3.0, 4.0];
+        let values = [0.0, 1.0, 2.0, 4.0];
+        let interpolate =
+            |interpolation, t| CameraTrack::new(interpolation).interpolate(times, values, t);
+
+        for interpolation in Interpolation::ALL {
+            assert_close(interpolate(interpolation, 0.0), 1.0);
+            assert_close(interpolate(interpolation, 1.0), 2.0);
+        }
+        assert_close(interpolate(Interpolation::Linear, 0.5), 1.5);
+        assert_close(interpolate(Interpolation::CatmullRom, 0.5), 1.4375);
+        assert_close(interpolate(Interpolation::Bezier, 0.5), 1.375);
+    }
+
+    #[test]
+    fn linear_data_stays_linear() {
+        for interpolation in Interpolation::ALL {
+            let track = track(interpolation, &[0.0, 1.0, 2.0, 3.0]);
+            let camera = track.sample(1.25).unwrap();
+            assert_close(camera.eye.x, 1.25);
+            assert_close(camera.fov, 1.0);
+        }
+    }
+
+    #[test]
+    fn ease() {
+        for ease in Ease::ALL {
+            assert_close(ease.apply(0.0), 0.0);
+            assert_close(ease.apply(1.0), 1.0);
+        }
+        assert_close(Ease::Linear.apply(0.25), 0.25);
+        assert_close(Ease::In.apply(0.5), 0.125);
+        assert_close(Ease::Out.apply(0.5), 0.875);
+        assert_close(Ease::InOut.apply(0.5), 0.5);
+        assert_close(Ease::InOut.apply(0.25), 0.15625);
+
+        // the ease of a key times the segment starting at it
+        let mut track = track(Interpolation::Linear, &[0.0, 2.0]);
+        let mut key = track.keys()[0];
+        key.ease = Ease::In;
+        track.set_key(0, key);
+        assert_close(track.sample(1.0).unwrap().eye.x, 0.125);
+    }
+
+    #[test]
+    fn set_key_reorders() {
+        let mut track = track(Interpolation::Linear, &[0.0, 1.0, 2.0]);
+        let ids: Vec<u32> = track.keys().iter().map(CameraKey::id).collect();
+
+        let mut key = track.keys()[0];
+        key.time = 1.5;
+        assert_eq!(track.set_key(0, key), 1);
+
+        let times: Vec<f32> = track.keys().iter().map(|k| k.time).collect();
+        assert_eq!(times, [1.0, 1.5, 2.0]);
+        // the moved key keeps its id
+        let moved: Vec<u32> = track.keys().iter().map(CameraKey::id).collect();
+        assert_eq!(moved, [ids[1], ids[0], ids[2]]);
+
+        // a key inserted at the time of another one replaces it
+        assert_eq!(track.insert_key(CameraKey::new(2.0, camera(5.0))), 2);
+        assert_eq!(track.keys().len(), 3);
+        assert_eq!(track.keys()[2].id(), ids[2]);
+    }
+
+    #[test]
+    fn ron_round_trip() {
+        let mut track = track(Interpolation::Bezier, &[0.0, 1.0, 3.0]);
+        let mut key = track.keys()[1];
+        key.ease = Ease::InOut;
+        track.set_key(1, key);
+
+        let path = std::env::temp_dir().join(format!("ray_march_track_{}.ron", std::process::id()));
+        track.save(&path).unwrap();
+        let loaded = CameraTrack::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded.interpolation, track.interpolation);
+        assert_eq!(loaded.keys().len(), track.keys().len());
+        for (a, b) in loaded.keys().iter().zip(track.keys()) {
+            assert_eq!((a.time, a.camera, a.ease), (b.time, b.camera, b.ease));
+        }
+        for t in [0.5, 1.0, 2.0, 2.9] {
+            assert_eq!(loaded.sample(t), track.sample(t));
+        }
+    }
+}
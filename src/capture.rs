@@ -0,0 +1,99 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::{headless::write_png, utils::ComputeUniforms};
+
+/// Settings stored next to a capture, enough to render it again.
+#[derive(Debug, Serialize)]
+struct CaptureInfo<'a> {
+    width: u32,
+    height: u32,
+    samples: u32,
+    uniforms: &'a ComputeUniforms,
+}
+
+/// Save `pixels` to `<dir>/screenshot_<timestamp>.png`, along with a json file holding the
+/// uniforms which produced them. Returns the path of the png.
+pub fn save_screenshot<P: AsRef<Path>>(
+    dir: P,
+    size: (u32, u32),
+    pixels: &[u8],
+    uniforms: &ComputeUniforms,
+    samples: u32,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(&dir)?;
+    let path = dir.as_ref().join(format!("screenshot_{}.png", timestamp()));
+    write_png(&path, size, pixels)?;
+
+    let info = CaptureInfo {
+        width: size.0,
+        height: size.1,
+        samples,
+        uniforms,
+    };
+    let json = serde_json::to_string_pretty(&info).map_err(io::Error::other)?;
+    fs::write(path.with_extension("json"), json)?;
+
+    Ok(path)
+}
+
+/// Current UTC time as `YYYY-MM-DD_HH-MM-SS-mmm`, usable in file names.
+fn timestamp() -> String {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+
+    format!(
+        "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}-{:03}",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+// date of a number of days since 1970-01-01, from
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(20744), (2026, 10, 18));
+    }
+
+    #[test]
+    fn leap_days() {
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        // not a leap year, divisible by 100 but not by 400
+        assert_eq!(civil_from_days(-25508), (1900, 3, 1));
+    }
+}
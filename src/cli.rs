@@ -0,0 +1,315 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::assets;
+
+pub const USAGE: &str = "\
+usage:
+    ray_march [options]             open the interactive viewer
+    ray_march render [options]      render a single frame offscreen
+    ray_march export [options]      render an animation offscreen
+    ray_march replay <file> [options]
+                                    render the frames of a recorded session offscreen
+
+options:
+    --scene <file>          scene description file (default: built-in scene)
+    --camera-track <file>   keyframed camera animation, played from u_time
+    --assets <dir>          read the shaders from <dir>/shaders and hot reload them, instead
+                            of the ones embedded in the executable (default: $RAY_MARCH_ASSETS)
+
+viewer options:
+    --record <file>         record the input of the session, saved when stopped or on exit
+    --replay <file>         replay a recorded session instead of the live input
+
+render, export and replay options:
+    --width <px>            image width (default: 1280)
+    --height <px>           image height (default: 720)
+    --path-trace            render with the path tracer instead of the ray marcher
+    --samples <n>           accumulated samples per pixel (default: 256 path tracing, 1 otherwise)
+    --bounces <n>           maximum bounces of the path tracer (default: 4)
+    --software              only accept a software adapter (lavapipe/llvmpipe)
+
+render options:
+    --out <file>            output png file (default: frame.png)
+    --time <seconds>        value of u_time (default: 0)
+
+export options:
+    --out <dir>             directory of the numbered png files (default: frames)
+    --fps <n>               frames per second (default: 30)
+    --start <seconds>       time of the first frame (default: 0)
+    --end <seconds>         time where the animation stops (default: 5)
+    --pipe <command>        shell command receiving the raw rgba frames on its stdin
+    --no-png                do not write the png files, e.g. when piping to an encoder
+
+replay options:
+    --out <dir>             directory of the numbered png files (default: frames)
+    --frame <n>             only write the frame <n>, counting from 0
+    --width, --height       default to the recorded resolution";
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    View(ViewOptions),
+    Render(RenderOptions),
+    Export(ExportOptions),
+    Replay(ReplayOptions),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ViewOptions {
+    pub scene: Option<PathBuf>,
+    pub camera_track: Option<PathBuf>,
+    pub assets: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+}
+
+/// Settings shared by the offscreen commands.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    pub scene: Option<PathBuf>,
+    pub camera_track: Option<PathBuf>,
+    pub assets: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub path_trace: bool,
+    pub samples: Option<u32>,
+    pub bounces: u32,
+    pub software: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            scene: None,
+            camera_track: None,
+            assets: None,
+            width: 1280,
+            height: 720,
+            path_trace: false,
+            samples: None,
+            bounces: 4,
+            software: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub settings: RenderSettings,
+    pub output: PathBuf,
+    pub time: f32,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            settings: RenderSettings::default(),
+            output: PathBuf::from("frame.png"),
+            time: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub settings: RenderSettings,
+    pub output_dir: PathBuf,
+    pub fps: f32,
+    pub start: f32,
+    pub end: f32,
+    pub pipe: Option<String>,
+    pub png: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            settings: RenderSettings::default(),
+            output_dir: PathBuf::from("frames"),
+            fps: 30.0,
+            start: 0.0,
+            end: 5.0,
+            pipe: None,
+            png: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub settings: RenderSettings,
+    /// `--width` or `--height` was given, the recorded resolution is not used.
+    pub resized: bool,
+    pub session: PathBuf,
+    pub output_dir: PathBuf,
+    pub frame: Option<usize>,
+}
+
+impl Command {
+    /// Directory of the shaders given by `--assets`, or else by `RAY_MARCH_ASSETS`.
+    pub fn assets_dir(&self) -> Option<PathBuf> {
+        let option = match self {
+            Command::View(opts) => &opts.assets,
+            Command::Render(opts) => &opts.settings.assets,
+            Command::Export(opts) => &opts.settings.assets,
+            Command::Replay(opts) => &opts.settings.assets,
+        };
+
+        option.clone().or_else(|| {
+            std::env::var_os(assets::ASSETS_ENV)
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from)
+        })
+    }
+}
+
+pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.peekable();
+    match args.peek().map(String::as_str) {
+        Some("render") => parse_render(args.skip(1)).map(Command::Render),
+        Some("export") => parse_export(args.skip(1)).map(Command::Export),
+        Some("replay") => parse_replay(args.skip(1)).map(Command::Replay),
+        Some(arg) if !arg.starts_with("--") => Err(format!("unknown command `{}`", arg)),
+        _ => parse_view(args).map(Command::View),
+    }
+}
+
+/// Options of the viewer. `AppInstance::create` has no way to receive them, so the
+/// app parses the command line again, it was already validated by `main`.
+pub fn view_options() -> ViewOptions {
+    match parse(std::env::args().skip(1)) {
+        Ok(Command::View(opts)) => opts,
+        _ => ViewOptions::default(),
+    }
+}
+
+fn parse_view<I: Iterator<Item = String>>(mut args: I) -> Result<ViewOptions, String> {
+    let mut opts = ViewOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => opts.scene = Some(value(&arg, &mut args)?),
+            "--camera-track" => opts.camera_track = Some(value(&arg, &mut args)?),
+            "--assets" => opts.assets = Some(value(&arg, &mut args)?),
+            "--record" => opts.record = Some(value(&arg, &mut args)?),
+            "--replay" => opts.replay = Some(value(&arg, &mut args)?),
+            _ => return Err(format!("unknown option `{}`", arg)),
+        }
+    }
+
+    Ok(opts)
+}
+
+fn parse_render<I: Iterator<Item = String>>(mut args: I) -> Result<RenderOptions, String> {
+    let mut opts = RenderOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => opts.output = value(&arg, &mut args)?,
+            "--time" => opts.time = value(&arg, &mut args)?,
+            _ => parse_settings(&arg, &mut args, &mut opts.settings)?,
+        }
+    }
+    check_settings(&opts.settings)?;
+
+    Ok(opts)
+}
+
+fn parse_export<I: Iterator<Item = String>>(mut args: I) -> Result<ExportOptions, String> {
+    let mut opts = ExportOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => opts.output_dir = value(&arg, &mut args)?,
+            "--fps" => opts.fps = value(&arg, &mut args)?,
+            "--start" => opts.start = value(&arg, &mut args)?,
+            "--end" => opts.end = value(&arg, &mut args)?,
+            "--pipe" => opts.pipe = Some(value(&arg, &mut args)?),
+            "--no-png" => opts.png = false,
+            _ => parse_settings(&arg, &mut args, &mut opts.settings)?,
+        }
+    }
+    check_settings(&opts.settings)?;
+
+    if opts.fps <= 0.0 {
+        return Err("the frame rate must be positive".to_string());
+    }
+    if opts.end <= opts.start {
+        return Err("the animation must end after it starts".to_string());
+    }
+    if !opts.png && opts.pipe.is_none() {
+        return Err("`--no-png` needs `--pipe`, nothing would be written".to_string());
+    }
+
+    Ok(opts)
+}
+
+fn parse_replay<I: Iterator<Item = String>>(mut args: I) -> Result<ReplayOptions, String> {
+    let session = match args.next() {
+        Some(arg) if !arg.starts_with("--") => PathBuf::from(arg),
+        _ => return Err("missing the session file to replay".to_string()),
+    };
+    let mut opts = ReplayOptions {
+        settings: RenderSettings::default(),
+        resized: false,
+        session,
+        output_dir: PathBuf::from("frames"),
+        frame: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => opts.output_dir = value(&arg, &mut args)?,
+            "--frame" => opts.frame = Some(value(&arg, &mut args)?),
+            "--width" | "--height" => {
+                opts.resized = true;
+                parse_settings(&arg, &mut args, &mut opts.settings)?
+            }
+            _ => parse_settings(&arg, &mut args, &mut opts.settings)?,
+        }
+    }
+    check_settings(&opts.settings)?;
+
+    Ok(opts)
+}
+
+/// Options of `RenderSettings`, shared by the offscreen commands.
+fn parse_settings<I: Iterator<Item = String>>(
+    arg: &str,
+    args: &mut I,
+    settings: &mut RenderSettings,
+) -> Result<(), String> {
+    match arg {
+        "--scene" => settings.scene = Some(value(arg, args)?),
+        "--camera-track" => settings.camera_track = Some(value(arg, args)?),
+        "--assets" => settings.assets = Some(value(arg, args)?),
+        "--width" => settings.width = value(arg, args)?,
+        "--height" => settings.height = value(arg, args)?,
+        "--path-trace" => settings.path_trace = true,
+        "--samples" => settings.samples = Some(value(arg, args)?),
+        "--bounces" => settings.bounces = value(arg, args)?,
+        "--software" => settings.software = true,
+        _ => return Err(format!("unknown option `{}`", arg)),
+    }
+
+    Ok(())
+}
+
+fn check_settings(settings: &RenderSettings) -> Result<(), String> {
+    if settings.width == 0 || settings.height == 0 {
+        return Err("image dimensions must be non zero".to_string());
+    }
+    if settings.samples == Some(0) {
+        return Err("at least one sample is needed".to_string());
+    }
+
+    Ok(())
+}
+
+fn value<T: FromStr, I: Iterator<Item = String>>(flag: &str, args: &mut I) -> Result<T, String> {
+    let raw = args
+        .next()
+        .ok_or_else(|| format!("missing value for `{}`", flag))?;
+    raw.parse()
+        .map_err(|_| format!("invalid value `{}` for `{}`", raw, flag))
+}
@@ -1,40 +1,310 @@
 use std::fs;
-use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileEvent {
+    Created(PathBuf),
+    Removed(PathBuf),
+    Modified(PathBuf),
+}
+
+impl FileEvent {
+    pub fn into_path(self) -> PathBuf {
+        match self {
+            FileEvent::Created(path) | FileEvent::Removed(path) | FileEvent::Modified(path) => path,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
-pub struct FileWatcher<'a> {
-    files: Vec<&'a Path>,
-    metadata: Vec<fs::Metadata>,
+struct WatchedFile {
+    path: PathBuf,
+    // modification time of the last reported state, `None` while the file is missing
+    reported: Option<SystemTime>,
+    last_seen: Option<SystemTime>,
+    changed_at: Option<Instant>,
 }
 
-impl<'a> FileWatcher<'a> {
-    pub fn new<T: AsRef<Path>>(files: &'a [T]) -> io::Result<Self> {
-        let mut metadata = Vec::with_capacity(files.len());
-        for file in files {
-            metadata.push(fs::metadata(file)?);
+impl WatchedFile {
+    fn new(path: &Path) -> Self {
+        let modified = modified_time(path);
+        Self {
+            path: path.to_path_buf(),
+            reported: modified,
+            last_seen: modified,
+            changed_at: None,
         }
-        Ok(Self {
-            files: files.iter().map(|f| f.as_ref()).collect(),
-            metadata,
-        })
     }
 
-    pub fn modified(&mut self) -> Vec<&'a Path> {
+    /// Look at the file again, an event is only returned once it stopped changing for
+    /// `debounce`, so a burst of writes or a rename-and-replace save gives a single event.
+    fn poll(&mut self, now: Instant, debounce: Duration) -> Option<FileEvent> {
+        let current = modified_time(&self.path);
+        if current != self.last_seen {
+            self.last_seen = current;
+            self.changed_at = Some(now);
+        }
+
+        match self.changed_at {
+            Some(t) if now.duration_since(t) >= debounce => self.changed_at = None,
+            _ => return None,
+        }
+
+        let event = match (self.reported, current) {
+            (None, Some(_)) => FileEvent::Created(self.path.clone()),
+            (Some(_), None) => FileEvent::Removed(self.path.clone()),
+            (Some(old), Some(new)) if old != new => FileEvent::Modified(self.path.clone()),
+            _ => return None,
+        };
+        self.reported = current;
+
+        Some(event)
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Watch a set of files, which may not exist yet.
+///
+/// Files are polled every time `events` is called. With the `notify` feature the
+/// directories of the files are watched by the os instead, and only the files the os
+/// reported are looked at.
+#[derive(Debug)]
+pub struct FileWatcher {
+    files: Vec<WatchedFile>,
+    #[cfg(feature = "notify")]
+    backend: Option<notify_backend::NotifyBackend>,
+}
+
+impl FileWatcher {
+    /// Time a file has to stop changing before its event is reported.
+    pub const DEBOUNCE: Duration = Duration::from_millis(50);
+
+    pub fn new<T: AsRef<Path>>(files: &[T]) -> Self {
+        let mut watcher = Self {
+            files: Vec::new(),
+            #[cfg(feature = "notify")]
+            backend: notify_backend::NotifyBackend::new()
+                .map_err(|e| eprintln!("file notifications unavailable, polling: {}", e))
+                .ok(),
+        };
+        watcher.set_files(files);
+
+        watcher
+    }
+
+    /// Watch `files` instead of the current ones, files already watched keep their state.
+    pub fn set_files<T: AsRef<Path>>(&mut self, files: &[T]) {
+        let previous = std::mem::take(&mut self.files);
+        self.files = files
+            .iter()
+            .map(|f| {
+                let f = f.as_ref();
+                match previous.iter().find(|w| w.path == f) {
+                    Some(watched) => watched.clone(),
+                    None => WatchedFile::new(f),
+                }
+            })
+            .collect();
+
+        #[cfg(feature = "notify")]
+        if let Some(backend) = &mut self.backend {
+            for file in &self.files {
+                backend.watch(&file.path);
+            }
+        }
+    }
+
+    pub fn events(&mut self) -> Vec<FileEvent> {
+        let now = Instant::now();
+
+        #[cfg(feature = "notify")]
+        if let Some(backend) = &mut self.backend {
+            let changed = backend.changed_files();
+            return self
+                .files
+                .iter_mut()
+                .filter(|f| f.changed_at.is_some() || changed.matches(&f.path))
+                .filter_map(|f| f.poll(now, Self::DEBOUNCE))
+                .collect();
+        }
+
         self.files
             .iter_mut()
-            .enumerate()
-            .filter_map(|(i, f)| {
-                let metadata = fs::metadata(f.clone()).unwrap();
-                let modified_time = metadata.modified().unwrap();
-
-                if modified_time > self.metadata[i].modified().unwrap() {
-                    self.metadata[i] = metadata;
-                    return Some(f.clone());
-                }
+            .filter_map(|f| f.poll(now, Self::DEBOUNCE))
+            .collect()
+    }
+}
+
+#[cfg(feature = "notify")]
+mod notify_backend {
+    use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+    use std::collections::HashSet;
+    use std::ffi::OsString;
+    use std::fmt;
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::Duration;
+
+    /// Files named by the os notifications since the last poll.
+    pub enum ChangedFiles {
+        All,
+        Named(HashSet<OsString>),
+    }
+
+    impl ChangedFiles {
+        // only the file names are compared, a false positive just costs a metadata
+        // lookup and the paths reported by the os may not be spelled like ours
+        pub fn matches(&self, path: &Path) -> bool {
+            match self {
+                ChangedFiles::All => true,
+                ChangedFiles::Named(names) => path.file_name().is_some_and(|n| names.contains(n)),
+            }
+        }
+    }
+
+    pub struct NotifyBackend {
+        watcher: RecommendedWatcher,
+        events: Receiver<DebouncedEvent>,
+        directories: HashSet<PathBuf>,
+    }
+
+    impl NotifyBackend {
+        pub fn new() -> notify::Result<Self> {
+            let (tx, events) = channel();
+            let watcher = notify::watcher(tx, Duration::from_millis(10))?;
 
-                None
+            Ok(Self {
+                watcher,
+                events,
+                directories: HashSet::new(),
             })
-            .collect()
+        }
+
+        /// Watch the directory of `file`, editors replacing the file on save would
+        /// break a watch on the file itself.
+        pub fn watch(&mut self, file: &Path) {
+            let directory = match file.parent() {
+                Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+                Some(dir) => dir,
+                None => return,
+            };
+
+            if self.directories.contains(directory) {
+                return;
+            }
+            match self.watcher.watch(directory, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    self.directories.insert(directory.to_path_buf());
+                }
+                Err(e) => eprintln!("could not watch {}: {}", directory.display(), e),
+            }
+        }
+
+        pub fn changed_files(&mut self) -> ChangedFiles {
+            let mut names = HashSet::new();
+
+            for event in self.events.try_iter() {
+                let paths = match event {
+                    DebouncedEvent::NoticeWrite(p)
+                    | DebouncedEvent::NoticeRemove(p)
+                    | DebouncedEvent::Create(p)
+                    | DebouncedEvent::Write(p)
+                    | DebouncedEvent::Chmod(p)
+                    | DebouncedEvent::Remove(p) => vec![p],
+                    DebouncedEvent::Rename(from, to) => vec![from, to],
+                    DebouncedEvent::Rescan | DebouncedEvent::Error(..) => return ChangedFiles::All,
+                };
+
+                names.extend(
+                    paths
+                        .iter()
+                        .filter_map(|p| p.file_name())
+                        .map(OsString::from),
+                );
+            }
+
+            ChangedFiles::Named(names)
+        }
+    }
+
+    impl fmt::Debug for NotifyBackend {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("NotifyBackend")
+                .field("directories", &self.directories)
+                .finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE: Duration = FileWatcher::DEBOUNCE;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Write `path` with a modification time `secs` after the epoch, so successive
+    /// writes are told apart whatever the resolution of the file system clock.
+    fn write(path: &Path, secs: u64) {
+        let file = fs::File::create(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+            .unwrap();
+    }
+
+    #[test]
+    fn debounced_events() {
+        let dir = std::env::temp_dir().join(format!("ray_march_watcher_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("shader.glsl");
+        let _ = fs::remove_file(&path);
+
+        let mut file = WatchedFile::new(&path);
+        let t = Instant::now();
+        assert_eq!(file.poll(t, DEBOUNCE), None);
+
+        write(&path, 1);
+        assert_eq!(file.poll(t, DEBOUNCE), None);
+        assert_eq!(
+            file.poll(t + DEBOUNCE, DEBOUNCE),
+            Some(FileEvent::Created(path.clone()))
+        );
+
+        // a burst of writes, each one before the previous settled
+        let t = t + ms(100);
+        for (i, secs) in [2, 3, 4].into_iter().enumerate() {
+            write(&path, secs);
+            assert_eq!(file.poll(t + ms(10 * i as u64), DEBOUNCE), None);
+        }
+        assert_eq!(file.poll(t + ms(30), DEBOUNCE), None);
+        assert_eq!(
+            file.poll(t + ms(20) + DEBOUNCE, DEBOUNCE),
+            Some(FileEvent::Modified(path.clone()))
+        );
+        assert_eq!(file.poll(t + ms(200), DEBOUNCE), None);
+
+        let t = t + ms(300);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(file.poll(t, DEBOUNCE), None);
+        assert_eq!(
+            file.poll(t + DEBOUNCE, DEBOUNCE),
+            Some(FileEvent::Removed(path.clone()))
+        );
+
+        // removed and written again before the event is reported
+        let t = t + ms(100);
+        write(&path, 5);
+        file.poll(t, DEBOUNCE);
+        fs::remove_file(&path).unwrap();
+        file.poll(t + ms(10), DEBOUNCE);
+        assert_eq!(file.poll(t + ms(10) + DEBOUNCE, DEBOUNCE), None);
+
+        let _ = fs::remove_dir_all(&dir);
     }
 }
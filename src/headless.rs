@@ -0,0 +1,451 @@
+use std::fs;
+use std::io::{self, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::process;
+
+use crate::{
+    camera::Camera,
+    camera_track::CameraTrack,
+    cli::{ExportOptions, RenderOptions, RenderSettings, ReplayOptions},
+    raymarch_pipeline::{RayMarchPipeline, RenderMode},
+    replay::Session,
+    scene::Scene,
+    utils::{create_output_texture, ComputeUniforms},
+    wgpu,
+};
+
+/// Device and queue created without any window or surface.
+#[derive(Debug)]
+pub struct HeadlessGpu {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+impl HeadlessGpu {
+    /// Request an adapter able to run the compute shader. With `software` set, only a
+    /// fallback adapter (lavapipe/llvmpipe) is accepted, otherwise it is used when no
+    /// hardware adapter is available.
+    pub fn new(software: bool) -> io::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::Backends::PRIMARY);
+
+        let request = |force_fallback_adapter| {
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter,
+                compatible_surface: None,
+            }))
+        };
+
+        let adapter = if software {
+            request(true)
+        } else {
+            request(false).or_else(|| request(true))
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no suitable adapter found"))?;
+
+        let info = adapter.get_info();
+        println!("using adapter {} ({:?})", info.name, info.backend);
+
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("headless_device"),
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+            },
+            None,
+        ))
+        .map_err(io::Error::other)?;
+
+        Ok(Self { device, queue })
+    }
+}
+
+/// Size of the rows of a staging buffer holding `width` rgba8 pixels per row, copies
+/// from textures need them aligned on `COPY_BYTES_PER_ROW_ALIGNMENT`.
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * 4).div_ceil(align) * align
+}
+
+/// Pixels of a staging buffer without the padding at the end of its rows.
+fn strip_row_padding(data: &[u8], width: u32) -> Vec<u8> {
+    let unpadded_bytes_per_row = (width * 4) as usize;
+    data.chunks(padded_bytes_per_row(width) as usize)
+        .flat_map(|row| row[..unpadded_bytes_per_row].iter().copied())
+        .collect()
+}
+
+/// Copy an `Rgba8Unorm` texture into a staging buffer and return its tightly packed pixels.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    size: (u32, u32),
+) -> Vec<u8> {
+    let padded_bytes_per_row = padded_bytes_per_row(size.0);
+
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback_buffer"),
+        size: (padded_bytes_per_row * size.1) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("readback_encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &staging_buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+                rows_per_image: None,
+            },
+        },
+        wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging_buffer.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).unwrap();
+
+    let pixels = strip_row_padding(&slice.get_mapped_range(), size.0);
+    staging_buffer.unmap();
+
+    pixels
+}
+
+/// Write linear rgba8 pixels to a png file. Values are gamma encoded like the sRGB
+/// swapchain of the viewer does, so the image looks the same as on screen.
+pub fn write_png<P: AsRef<Path>>(path: P, size: (u32, u32), pixels: &[u8]) -> io::Result<()> {
+    let file = fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(io::BufWriter::new(file), size.0, size.1);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&srgb_pixels(pixels))?;
+
+    Ok(())
+}
+
+fn srgb_pixels(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks(4)
+        .flat_map(|px| [srgb(px[0]), srgb(px[1]), srgb(px[2]), px[3]])
+        .collect()
+}
+
+fn srgb(value: u8) -> u8 {
+    let linear = value as f32 / 255.0;
+    let encoded = if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+
+    (encoded * 255.0).round() as u8
+}
+
+/// Scene rendered without a window, by the render and export commands.
+struct OffscreenRenderer {
+    gpu: HeadlessGpu,
+    // CPU side of the scene, for the camera actions of replays
+    scene: Scene,
+    raymarch_pipeline: RayMarchPipeline,
+    output_texture: wgpu::Texture,
+    size: (u32, u32),
+    camera: Camera,
+    camera_track: Option<CameraTrack>,
+    bounces: u32,
+    samples: u32,
+}
+
+impl OffscreenRenderer {
+    fn new(settings: &RenderSettings) -> io::Result<Self> {
+        let scene = match &settings.scene {
+            Some(path) => {
+                Some(Scene::load(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?)
+            }
+            None => None,
+        };
+        let camera = scene.as_ref().map_or_else(Camera::initial, |s| s.camera);
+        let camera_track = settings
+            .camera_track
+            .as_ref()
+            .map(CameraTrack::load)
+            .transpose()?;
+
+        let gpu = HeadlessGpu::new(settings.software)?;
+        let size = (settings.width, settings.height);
+
+        let output_texture = create_output_texture(&gpu.device, size);
+        let output_view = output_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut raymarch_pipeline =
+            RayMarchPipeline::new(&gpu.device, &output_view, size, scene.as_ref());
+        // the viewer falls back to the embedded shaders, an offline render must not
+        let errors = raymarch_pipeline.shader_errors();
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("shader errors:\n{}", errors.join("\n")),
+            ));
+        }
+
+        let (mode, default_samples) = match settings.path_trace {
+            true => (RenderMode::PathTrace, 256),
+            false => (RenderMode::RayMarch, 1),
+        };
+        let samples = settings.samples.unwrap_or(default_samples);
+        raymarch_pipeline.set_render_mode(mode);
+        raymarch_pipeline.set_accumulation(samples > 1);
+
+        Ok(Self {
+            gpu,
+            scene: scene.unwrap_or_else(Scene::builtin),
+            raymarch_pipeline,
+            output_texture,
+            size,
+            camera,
+            camera_track,
+            bounces: settings.bounces,
+            samples,
+        })
+    }
+
+    /// Render the frame at `time` and return its linear rgba8 pixels. The camera track,
+    /// if any, is sampled at the same time.
+    fn render(&mut self, time: f32) -> Vec<u8> {
+        let camera = self
+            .camera_track
+            .as_ref()
+            .and_then(|track| track.sample(time))
+            .unwrap_or(self.camera);
+        self.render_view(camera, time)
+    }
+
+    fn render_view(&mut self, camera: Camera, time: f32) -> Vec<u8> {
+        let mut uniforms = ComputeUniforms::new(camera, time);
+        uniforms.max_bounces = self.bounces as i32;
+        self.raymarch_pipeline
+            .upload_uniforms(&self.gpu.queue, &uniforms);
+        self.raymarch_pipeline.reset_accumulation();
+
+        for _ in 0..self.samples {
+            self.raymarch_pipeline
+                .execute(&self.gpu.device, &self.gpu.queue, self.size);
+        }
+
+        read_texture(
+            &self.gpu.device,
+            &self.gpu.queue,
+            &self.output_texture,
+            self.size,
+        )
+    }
+}
+
+pub fn render(opts: &RenderOptions) -> io::Result<()> {
+    let mut renderer = OffscreenRenderer::new(&opts.settings)?;
+
+    let pixels = renderer.render(opts.time);
+    write_png(&opts.output, renderer.size, &pixels)?;
+    println!("frame written to {}", opts.output.display());
+
+    Ok(())
+}
+
+/// Spawn `command` in a shell, with a pipe to its stdin.
+fn spawn_encoder(command: &str) -> io::Result<process::Child> {
+    #[cfg(windows)]
+    let mut shell = process::Command::new("cmd");
+    #[cfg(windows)]
+    shell.arg("/C");
+    #[cfg(not(windows))]
+    let mut shell = process::Command::new("sh");
+    #[cfg(not(windows))]
+    shell.arg("-c");
+
+    shell.arg(command).stdin(process::Stdio::piped()).spawn()
+}
+
+/// Number of frames from `start` to `end` at `fps`, the last one starting before `end`.
+/// Rounding errors such as `0.1 * 30.0 = 3.0000002` do not add a frame.
+fn frame_count(start: f32, end: f32, fps: f32) -> u32 {
+    ((end - start) * fps - 1e-4).ceil().max(0.0) as u32
+}
+
+/// Time of the frame number `frame` of an export starting at `start`.
+fn frame_time(start: f32, fps: f32, frame: u32) -> f32 {
+    start + frame as f32 / fps
+}
+
+/// Render the frames from `start` to `end` at a fixed frame rate, the time of each frame
+/// only depends on its number so exports are reproducible.
+pub fn export(opts: &ExportOptions) -> io::Result<()> {
+    let mut renderer = OffscreenRenderer::new(&opts.settings)?;
+    if opts.png {
+        fs::create_dir_all(&opts.output_dir)?;
+    }
+    let mut encoder = opts.pipe.as_deref().map(spawn_encoder).transpose()?;
+
+    let frame_count = frame_count(opts.start, opts.end, opts.fps);
+    for frame in 0..frame_count {
+        let time = frame_time(opts.start, opts.fps, frame);
+        let pixels = renderer.render(time);
+
+        if opts.png {
+            let path = opts.output_dir.join(format!("frame_{:05}.png", frame));
+            write_png(&path, renderer.size, &pixels)?;
+        }
+        if let Some(encoder) = &mut encoder {
+            encoder
+                .stdin
+                .as_mut()
+                .unwrap()
+                .write_all(&srgb_pixels(&pixels))?;
+        }
+
+        println!("frame {}/{} (t = {:.3}s)", frame + 1, frame_count, time);
+    }
+
+    if let Some(mut encoder) = encoder {
+        // closing stdin ends the stream
+        drop(encoder.stdin.take());
+        let status = encoder.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("encoder exited with {}", status)));
+        }
+    }
+    if opts.png {
+        println!("frames written to {}", opts.output_dir.display());
+    }
+
+    Ok(())
+}
+
+/// Play a recorded session through the camera controller and write its frames. Frames
+/// before `--frame` are simulated but not rendered.
+pub fn replay(opts: &ReplayOptions) -> io::Result<()> {
+    let session = Session::load(&opts.session)?;
+    let mut settings = opts.settings.clone();
+    if settings.scene.is_none() {
+        settings.scene = session.scene.clone();
+    }
+    if !opts.resized {
+        (settings.width, settings.height) = session.resolution;
+    }
+    if opts.frame.is_some_and(|f| f >= session.frames.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the session only has {} frames", session.frames.len()),
+        ));
+    }
+
+    let mut renderer = OffscreenRenderer::new(&settings)?;
+    fs::create_dir_all(&opts.output_dir)?;
+
+    let mut controller = session.controller;
+    for (i, frame) in session.frames.iter().enumerate() {
+        frame.apply(&mut controller, &renderer.scene);
+        if opts.frame.is_some_and(|f| f != i) {
+            continue;
+        }
+
+        let pixels = renderer.render_view(controller.camera, frame.time);
+        let path = opts.output_dir.join(format!("frame_{:05}.png", i));
+        write_png(&path, renderer.size, &pixels)?;
+        println!(
+            "frame {}/{} (t = {:.3}s)",
+            i + 1,
+            session.frames.len(),
+            frame.time
+        );
+    }
+    println!("frames written to {}", opts.output_dir.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_frames() {
+        assert_eq!(frame_count(0.0, 0.1, 30.0), 3);
+        assert_eq!(frame_count(1.0, 1.1, 30.0), 3);
+        assert_eq!(frame_count(0.0, 2.0, 24.0), 48);
+        // a partial frame at the end is rendered
+        assert_eq!(frame_count(0.0, 0.11, 30.0), 4);
+        assert_eq!(frame_count(0.0, 0.0, 30.0), 0);
+        assert_eq!(frame_count(1.0, 0.0, 30.0), 0);
+
+        let times: Vec<f32> = (0..3).map(|f| frame_time(0.5, 30.0, f)).collect();
+        for (time, expected) in times.iter().zip([0.5, 0.5 + 1.0 / 30.0, 0.5 + 2.0 / 30.0]) {
+            assert!((time - expected).abs() < 1e-6, "{} != {}", time, expected);
+        }
+    }
+
+    #[test]
+    fn row_padding() {
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+        assert_eq!(padded_bytes_per_row(1366), 5632);
+    }
+
+    #[test]
+    fn strip_padding_of_odd_width() {
+        // 3 rows of 70 pixels, each padded from 280 to 512 bytes
+        let width = 70;
+        let padded = padded_bytes_per_row(width) as usize;
+        assert_eq!(padded, 512);
+        let mut data = vec![0xff; padded * 3];
+        for y in 0..3 {
+            for x in 0..width as usize * 4 {
+                data[y * padded + x] = (y * 7 + x) as u8;
+            }
+        }
+
+        let pixels = strip_row_padding(&data, width);
+        assert_eq!(pixels.len(), width as usize * 4 * 3);
+        for (i, row) in pixels.chunks(width as usize * 4).enumerate() {
+            assert!(row.iter().enumerate().all(|(x, b)| *b == (i * 7 + x) as u8));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a gpu or a software adapter"]
+    fn render_covers_odd_resolution() {
+        let settings = RenderSettings {
+            width: 1366,
+            height: 768,
+            ..RenderSettings::default()
+        };
+        let mut renderer = OffscreenRenderer::new(&settings).unwrap();
+        let pixels = renderer.render(0.0);
+
+        // the shader writes opaque pixels over a transparent texture, so the last row
+        // and column are only opaque when the dispatch reached them
+        let (width, height) = renderer.size;
+        let alpha = |x: u32, y: u32| pixels[((y * width + x) * 4 + 3) as usize];
+        assert!((0..width).all(|x| alpha(x, height - 1) == 255));
+        assert!((0..height).all(|y| alpha(width - 1, y) == 255));
+    }
+}
@@ -0,0 +1,17 @@
+pub mod assets;
+pub mod camera;
+pub mod camera_track;
+pub mod capture;
+pub mod cli;
+pub mod filewatcher;
+pub mod headless;
+pub mod raymarch_pipeline;
+pub mod reflection;
+pub mod replay;
+pub mod scene;
+pub mod sdf;
+pub mod shader_graph;
+pub mod shader_params;
+pub mod utils;
+
+use wgpu_sandbox::prelude::wgpu;
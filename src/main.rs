@@ -1,41 +1,108 @@
-mod camera;
-mod filewatcher;
-mod raymarch_pipeline;
-mod utils;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
-use glam::vec3;
-use std::time::{Duration, Instant};
-
-use camera::{Camera, CameraController};
-use raymarch_pipeline::RayMarchPipeline;
-use utils::{load_spirv_shader, ComputeUniforms};
+use ray_march::{
+    assets,
+    camera::{ButtonMapping, Camera, CameraController, CameraMode, MouseAction},
+    camera_track::{CameraKey, CameraTrack, Ease, Interpolation},
+    capture,
+    cli::{self, Command},
+    headless::{self, read_texture},
+    raymarch_pipeline::{RayMarchPipeline, RenderMode, RenderTarget, ShaderDefines, ShadingMode},
+    reflection::Scalar,
+    replay::{Player, Recorder, Session},
+    scene::{default_lights, default_material_table, LightKind, Scene, SceneLight, SceneMaterial},
+    shader_params::{ShaderParams, Widget},
+    utils::{create_output_texture, load_spirv_shader, ComputeUniforms, Light},
+};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu_sandbox::prelude::*;
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+const WINDOW_WIDTH: u32 = 1280;
+const WINDOW_HEIGHT: u32 = 720;
+const SCREENSHOT_DIR: &str = "./screenshots";
+// samples of a supersampled screenshot, rendered one per frame
+const SCREENSHOT_MAX_SAMPLES: u32 = 256;
+const CAMERA_TRACK_FILE: &str = "camera_track.ron";
+const SESSION_FILE: &str = "session.ron";
+
+fn create_render_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("render_pipeline_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
 
-const TEXTURE_WIDTH: u32 = 1280;
-const TEXTURE_HEIGHT: u32 = 720;
-const WORKGROUP_LOCAL_SIZE: (u32, u32) = (16, 16);
-const WORKGROUP_SIZE: (u32, u32) = (
-    TEXTURE_WIDTH / WORKGROUP_LOCAL_SIZE.0,
-    TEXTURE_HEIGHT / WORKGROUP_LOCAL_SIZE.1,
-);
+/// Supersampled screenshot being rendered, see [`MainApp::render_screenshot`].
+#[derive(Debug)]
+struct PendingScreenshot {
+    texture: wgpu::Texture,
+    target: RenderTarget,
+    samples: u32,
+}
 
 #[derive(Debug)]
-pub struct MainApp<'a> {
+pub struct MainApp {
     render_pipeline: wgpu::RenderPipeline,
+    render_bind_group_layout: wgpu::BindGroupLayout,
     render_bind_group: wgpu::BindGroup,
+    render_texture: wgpu::Texture,
+    render_sampler: wgpu::Sampler,
     indices_buffer: wgpu::Buffer,
     vertices_buffer: wgpu::Buffer,
-    raymarch_pipeline: RayMarchPipeline<'a>,
+    raymarch_pipeline: RayMarchPipeline,
     camera_controller: CameraController,
+    camera_track: CameraTrack,
+    camera_track_path: String,
+    camera_track_status: Option<String>,
+    play_camera_track: bool,
+    // CPU side of the rendered scene, for picking and framing
+    scene: Scene,
+    scene_path: Option<PathBuf>,
+    recorder: Option<Recorder>,
+    player: Option<Player>,
+    session_path: String,
+    session_status: Option<String>,
     compute_uniforms: ComputeUniforms,
-    clock: Instant,
+    materials: Vec<SceneMaterial>,
+    lights: Vec<SceneLight>,
+    time: f32,
+    animate: bool,
+    accumulate: bool,
+    window_size: (u32, u32),
+    render_scale: f32,
+    max_texture_size: u32,
+    resize_pending: bool,
+    screenshot_pending: bool,
+    screenshot_scale: u32,
+    screenshot: Option<PendingScreenshot>,
+    // last saved screenshot or error
+    screenshot_status: Option<String>,
+    // edited by the ui, applied once a widget is released
+    shader_defines: ShaderDefines,
     run_shader: bool,
     enable_hot_reload: bool,
     ui_take_input: bool,
 }
 
-impl<'a> AppInstance for MainApp<'a> {
+impl AppInstance for MainApp {
     fn create(gpu: &Gpu) -> Self {
         // loading vertices and indices buffers for the canvas
         let vertices_buffer = gpu.device.create_buffer_init(&BufferInitDescriptor {
@@ -51,17 +118,18 @@ impl<'a> AppInstance for MainApp<'a> {
         });
 
         // loading shaders
-        let vs_mod =
-            load_spirv_shader("assets/compiled_shaders/quad.vert.spv", &gpu.device).unwrap();
-        let fs_mod =
-            load_spirv_shader("assets/compiled_shaders/quad.frag.spv", &gpu.device).unwrap();
-
-        // create texture
-        let render_texture = TextureBuilder::new()
-            .with_format(wgpu::TextureFormat::Rgba8Unorm)
-            .with_usages(wgpu::TextureUsages::STORAGE_BINDING)
-            .with_data(&[0; (TEXTURE_WIDTH * TEXTURE_HEIGHT * 4) as usize])
-            .build((TEXTURE_WIDTH, TEXTURE_HEIGHT), &gpu.device, &gpu.queue);
+        let vs_mod = load_spirv_shader("quad.vert", &gpu.device);
+        let fs_mod = load_spirv_shader("quad.frag", &gpu.device);
+
+        // create texture, resized along with the window
+        let render_texture = create_output_texture(&gpu.device, (WINDOW_WIDTH, WINDOW_HEIGHT));
+        let render_view = render_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let render_sampler = gpu.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("render_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
 
         // initialize render pipeline
         let render_pipeline_bind_group_layout =
@@ -88,20 +156,12 @@ impl<'a> AppInstance for MainApp<'a> {
                     ],
                 });
 
-        let render_pipeline_bind_group = gpu.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("render_pipeline_bind_group"),
-            layout: &render_pipeline_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&render_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&render_texture.sampler),
-                },
-            ],
-        });
+        let render_pipeline_bind_group = create_render_bind_group(
+            &gpu.device,
+            &render_pipeline_bind_group_layout,
+            &render_view,
+            &render_sampler,
+        );
 
         // building render pipeline
         let pipeline_layout = gpu
@@ -140,26 +200,102 @@ impl<'a> AppInstance for MainApp<'a> {
                 multisample: wgpu::MultisampleState::default(),
             });
 
-        // init raymarching context
-        let raymarch_pipeline = RayMarchPipeline::new(&gpu.device, &render_texture);
-        let camera_controller = CameraController::new(
-            Camera::new(vec3(5.0, 5.0, 5.0), vec3(0.0, 0.0, 0.0), 1.5),
-            (TEXTURE_WIDTH as f32, TEXTURE_HEIGHT as f32),
+        // init raymarching context, the scene, track and session files were checked by
+        // main. A replay starts from the recorded scene unless another one is given
+        let opts = cli::view_options();
+        let session = opts
+            .replay
+            .as_ref()
+            .map(|path| Session::load(path).unwrap());
+        let scene_path = opts
+            .scene
+            .clone()
+            .or_else(|| session.as_ref().and_then(|s| s.scene.clone()));
+        let scene = scene_path.as_ref().map(|path| Scene::load(path).unwrap());
+        let camera_track = opts
+            .camera_track
+            .as_ref()
+            .map_or_else(CameraTrack::default, |path| {
+                CameraTrack::load(path).unwrap()
+            });
+        let camera_track_path = opts
+            .camera_track
+            .map_or(CAMERA_TRACK_FILE.to_string(), |p| p.display().to_string());
+        let mut raymarch_pipeline = RayMarchPipeline::new(
+            &gpu.device,
+            &render_view,
+            (WINDOW_WIDTH, WINDOW_HEIGHT),
+            scene.as_ref(),
         );
+        let materials = scene
+            .as_ref()
+            .map_or_else(default_material_table, Scene::material_table);
+        let lights = scene
+            .as_ref()
+            .map_or_else(default_lights, |s| s.lights.clone());
+        let camera_controller = match &session {
+            Some(session) => session.controller,
+            None => CameraController::new(
+                scene.as_ref().map_or_else(Camera::initial, |s| s.camera),
+                (WINDOW_WIDTH as f32, WINDOW_HEIGHT as f32),
+            ),
+        };
         let compute_uniforms = ComputeUniforms::new(camera_controller.camera, 0.0);
-        raymarch_pipeline.upload_uniforms(&gpu, &compute_uniforms);
+        raymarch_pipeline.upload_uniforms(&gpu.queue, &compute_uniforms);
+
+        let player = session.map(Player::new);
+        // the render texture takes the resolution of the replayed session
+        let resize_pending = player.is_some();
+        let recorder = opts.record.as_ref().map(|_| {
+            Recorder::new(
+                scene_path.clone(),
+                (WINDOW_WIDTH, WINDOW_HEIGHT),
+                camera_controller,
+            )
+        });
+        let session_path = opts
+            .record
+            .as_ref()
+            .or(opts.replay.as_ref())
+            .map_or(SESSION_FILE.to_string(), |p| p.display().to_string());
 
         Self {
             render_pipeline,
             vertices_buffer,
             indices_buffer,
+            render_bind_group_layout: render_pipeline_bind_group_layout,
             render_bind_group: render_pipeline_bind_group,
+            render_texture,
+            render_sampler,
             raymarch_pipeline,
             camera_controller,
+            play_camera_track: !camera_track.is_empty() && recorder.is_none() && player.is_none(),
+            camera_track,
+            camera_track_path,
+            camera_track_status: None,
+            scene: scene.unwrap_or_else(Scene::builtin),
+            scene_path,
+            recorder,
+            player,
+            session_path,
+            session_status: None,
             compute_uniforms,
-            clock: Instant::now(),
+            materials,
+            lights,
+            time: 0.0,
+            animate: true,
+            accumulate: false,
+            window_size: (WINDOW_WIDTH, WINDOW_HEIGHT),
+            render_scale: 1.0,
+            max_texture_size: gpu.device.limits().max_texture_dimension_2d,
+            resize_pending,
+            screenshot_pending: false,
+            screenshot_scale: 1,
+            screenshot: None,
+            screenshot_status: None,
+            shader_defines: ShaderDefines::default(),
             run_shader: true,
-            enable_hot_reload: true,
+            enable_hot_reload: assets::dir().is_some(),
             ui_take_input: false,
         }
     }
@@ -199,33 +335,101 @@ impl<'a> AppInstance for MainApp<'a> {
         gpu.queue.submit(std::iter::once(render_encoder.finish()));
     }
 
-    fn events(&mut self, event: &winit::event::WindowEvent) {
-        if !self.ui_take_input {
+    fn events(&mut self, event: &WindowEvent) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_event(event);
+        }
+
+        match event {
+            // minimized windows have a null size
+            WindowEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                self.window_size = (size.width, size.height);
+                if self.player.is_none() {
+                    self.camera_controller
+                        .set_screen_size((size.width as f32, size.height as f32));
+                }
+                self.resize_pending = true;
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::F12),
+                        ..
+                    },
+                ..
+            } => self.screenshot_pending = true,
+            _ => {}
+        }
+
+        // a replay ignores the live input
+        if !self.ui_take_input && self.player.is_none() {
             self.camera_controller.handle_events(event);
         }
     }
 
     fn update(&mut self, gpu: &Gpu, dt: Duration) {
-        let time = self.clock.elapsed().as_secs_f32();
-        self.camera_controller.update(dt);
+        if let Some(player) = &mut self.player {
+            match player.next_frame() {
+                Some(frame) => {
+                    self.time = frame.time;
+                    frame.apply(&mut self.camera_controller, &self.scene);
+                }
+                None => {
+                    println!("replay finished");
+                    self.player = None;
+                    self.resize_pending = true;
+                }
+            }
+        } else {
+            // a new time would reset the accumulation every frame, so it never converges,
+            // unless a camera track is played which moves the camera anyway
+            let paused = self.raymarch_pipeline.accumulating() && !self.play_camera_track;
+            if self.animate && !paused {
+                self.time += dt.as_secs_f32();
+            }
+            self.camera_controller.update(dt);
+            self.camera_controller.scene_actions(&self.scene);
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.end_frame(dt, self.time, self.ui_take_input);
+        }
+        if self.play_camera_track {
+            if let Some(camera) = self.camera_track.sample(self.time) {
+                self.camera_controller.camera = camera;
+            }
+        }
 
         // update uniforms
-        self.compute_uniforms.update_time(time);
+        self.compute_uniforms.update_time(self.time);
         self.compute_uniforms
             .update_camera(self.camera_controller.camera);
         self.raymarch_pipeline
-            .upload_uniforms(&gpu, &self.compute_uniforms);
+            .upload_uniforms(&gpu.queue, &self.compute_uniforms);
 
         if self.enable_hot_reload {
-            self.raymarch_pipeline.update_shader(&gpu);
+            self.raymarch_pipeline.update_shader();
+        }
+        self.raymarch_pipeline.receive_shaders(&gpu.device);
+
+        if self.resize_pending {
+            self.resize_render_texture(gpu);
         }
 
         if self.run_shader {
-            self.raymarch_pipeline.execute(&gpu, WORKGROUP_SIZE);
+            let size = self.render_size();
+            self.raymarch_pipeline
+                .execute(&gpu.device, &gpu.queue, size);
+        }
+
+        if self.screenshot_pending {
+            self.screenshot_pending = false;
+            self.take_screenshot(gpu);
         }
+        self.render_screenshot(gpu);
     }
 
-    fn on_imgui(&mut self, ui: &imgui::Ui, _gpu: &Gpu, dt: Duration) {
+    fn on_imgui(&mut self, ui: &imgui::Ui, gpu: &Gpu, dt: Duration) {
         let dt = dt.as_secs_f32();
 
         imgui::Window::new("Control").build(&ui, || {
@@ -238,18 +442,707 @@ impl<'a> AppInstance for MainApp<'a> {
             ui.separator();
             ui.text("Hello");
             ui.checkbox("run", &mut self.run_shader);
-            ui.checkbox("hot reload", &mut self.enable_hot_reload);
+            if assets::dir().is_some() {
+                ui.checkbox("hot reload", &mut self.enable_hot_reload);
+            } else {
+                ui.text("embedded shaders, --assets to hot reload");
+            }
+            if self.raymarch_pipeline.is_compiling() {
+                ui.same_line();
+                ui.text_colored([1.0, 0.8, 0.2, 1.0], "compiling...");
+            }
+            ui.checkbox("animate", &mut self.animate);
+            if ui.checkbox("accumulate", &mut self.accumulate) {
+                self.raymarch_pipeline.set_accumulation(self.accumulate);
+            }
+
+            let mut mode = RenderMode::ALL
+                .iter()
+                .position(|m| *m == self.raymarch_pipeline.render_mode())
+                .unwrap();
+            let mode_names: Vec<_> = RenderMode::ALL.iter().map(|m| m.name()).collect();
+            if ui.combo_simple_string("mode", &mut mode, &mode_names) {
+                self.raymarch_pipeline
+                    .set_render_mode(RenderMode::ALL[mode]);
+            }
+            if RenderMode::ALL[mode] == RenderMode::PathTrace {
+                imgui::Slider::new("bounces", 1, 16)
+                    .build(ui, &mut self.compute_uniforms.max_bounces);
+            } else {
+                let mut shading = self.compute_uniforms.shading_mode as usize;
+                let shading_names: Vec<_> = ShadingMode::ALL.iter().map(|m| m.name()).collect();
+                if ui.combo_simple_string("shading", &mut shading, &shading_names) {
+                    self.compute_uniforms.shading_mode = ShadingMode::ALL[shading] as i32;
+                }
+            }
+            if self.raymarch_pipeline.accumulating() {
+                ui.text(format!("{} samples", self.raymarch_pipeline.sample_count()));
+                if self.animate && !self.play_camera_track {
+                    ui.text_disabled("time is paused while accumulating");
+                }
+            }
+
+            // the tab key is recorded, the widgets are not
+            let session = ui.begin_disabled(self.session_active());
+            let mut camera_mode = CameraMode::ALL
+                .iter()
+                .position(|m| *m == self.camera_controller.mode())
+                .unwrap();
+            let camera_mode_names: Vec<_> = CameraMode::ALL.iter().map(|m| m.name()).collect();
+            if ui.combo_simple_string("camera (tab)", &mut camera_mode, &camera_mode_names) {
+                self.camera_controller
+                    .set_mode(CameraMode::ALL[camera_mode]);
+            }
+            if self.camera_controller.mode() == CameraMode::Fly {
+                imgui::Slider::new("fly speed", 0.01, 100.0)
+                    .build(ui, &mut self.camera_controller.fly_speed);
+                ui.text("WASD/QE to move, shift/ctrl to go faster/slower");
+            } else if ui.button("frame scene (F)") {
+                self.camera_controller.frame_scene(&self.scene);
+            }
+            if ui.collapsing_header("Camera controls", imgui::TreeNodeFlags::empty()) {
+                camera_controls(ui, &mut self.camera_controller);
+            }
+            session.end();
+
+            if imgui::Slider::new("render scale", 0.25, 2.0).build(ui, &mut self.render_scale) {
+                self.resize_pending = true;
+            }
+            let size = self.render_size();
+            ui.text(format!("resolution : {}x{}", size.0, size.1));
+
+            if ui.button("screenshot (F12)") {
+                self.screenshot_pending = true;
+            }
+            imgui::Slider::new("supersampling", 1, 4).build(ui, &mut self.screenshot_scale);
+            let scale = self.supersampling();
+            if scale < self.screenshot_scale {
+                ui.text(format!("limited to {} by the device", scale));
+            }
+            if let Some(screenshot) = &self.screenshot {
+                ui.text(format!(
+                    "rendering screenshot : {}/{} samples",
+                    screenshot.target.sample_count(),
+                    screenshot.samples
+                ));
+            } else if let Some(status) = &self.screenshot_status {
+                ui.text(status);
+            }
+
+            ui.separator();
+            self.session_controls(ui);
+
+            if ui.collapsing_header("Shader defines", imgui::TreeNodeFlags::empty())
+                && shader_defines_editor(ui, &mut self.shader_defines)
+            {
+                self.raymarch_pipeline.set_defines(self.shader_defines);
+            }
+
+            if ui.collapsing_header("Shader parameters", imgui::TreeNodeFlags::empty())
+                && shader_params_editor(ui, self.raymarch_pipeline.shader_params_mut())
+            {
+                self.raymarch_pipeline.reset_accumulation();
+            }
+
+            if ui.collapsing_header("Lights", imgui::TreeNodeFlags::empty())
+                && light_editor(ui, &mut self.lights)
+            {
+                self.raymarch_pipeline
+                    .upload_lights(&gpu.queue, &self.lights);
+            }
 
             self.ui_take_input = ui.is_window_focused();
         });
+
+        imgui::Window::new("Materials").build(&ui, || {
+            let mut changed = false;
+            for (i, mat) in self.materials.iter_mut().enumerate() {
+                if !ui.collapsing_header(
+                    format!("{} ({})##material{}", mat.name, i, i),
+                    imgui::TreeNodeFlags::empty(),
+                ) {
+                    continue;
+                }
+
+                let id = ui.push_id(i as i32);
+                changed |= imgui::ColorEdit::new("diffuse", mat.diffuse.as_mut()).build(ui);
+                changed |= imgui::ColorEdit::new("ambient", mat.ambient.as_mut()).build(ui);
+                changed |= imgui::ColorEdit::new("specular", mat.specular.as_mut()).build(ui);
+                changed |= imgui::Slider::new("exponent", 1.0, 256.0)
+                    .build(ui, &mut mat.specular_exponent);
+                changed |= imgui::Drag::new("emission")
+                    .speed(0.01)
+                    .range(0.0, 100.0)
+                    .build_array(ui, mat.emission.as_mut());
+                id.pop();
+            }
+
+            if changed {
+                self.raymarch_pipeline
+                    .upload_materials(&gpu.queue, &self.materials);
+            }
+
+            self.ui_take_input |= ui.is_window_focused();
+        });
+
+        imgui::Window::new("Camera track").build(&ui, || {
+            self.camera_track_editor(ui);
+            self.ui_take_input |= ui.is_window_focused();
+        });
+
+        let shader_errors = self.raymarch_pipeline.shader_errors();
+        if !shader_errors.is_empty() {
+            imgui::Window::new("Shader errors").build(&ui, || {
+                ui.text("the previous shader is still running");
+                ui.separator();
+                for error in shader_errors {
+                    ui.text_colored([1.0, 0.4, 0.4, 1.0], error.to_string());
+                }
+            });
+        }
+    }
+}
+
+impl MainApp {
+    /// Resolution of the ray marched image, the window size scaled by `render_scale` or
+    /// the recorded resolution during a replay, limited to the largest texture of the
+    /// device.
+    fn render_size(&self) -> (u32, u32) {
+        let size = match &self.player {
+            Some(player) => player.session().resolution,
+            None => {
+                let scale = |v: u32| (v as f32 * self.render_scale).round() as u32;
+                (scale(self.window_size.0), scale(self.window_size.1))
+            }
+        };
+        let clamp = |v: u32| v.clamp(1, self.max_texture_size);
+        (clamp(size.0), clamp(size.1))
+    }
+
+    /// `screenshot_scale`, reduced so the supersampled image fits in the largest texture
+    /// supported by the device.
+    fn supersampling(&self) -> u32 {
+        let (width, height) = self.render_size();
+        let max_scale = self.max_texture_size / width.max(height);
+        self.screenshot_scale.min(max_scale).max(1)
+    }
+
+    /// Save the ray marched image, without the ui. With a `screenshot_scale` above 1 the
+    /// image is rendered again at a larger resolution, in its own target so the
+    /// accumulation on screen goes on, see [`MainApp::render_screenshot`].
+    fn take_screenshot(&mut self, gpu: &Gpu) {
+        if self.screenshot.is_some() {
+            return;
+        }
+
+        let samples = self.raymarch_pipeline.sample_count().max(1);
+        let size = self.render_size();
+        let scale = self.supersampling();
+        if scale <= 1 {
+            let pixels = read_texture(&gpu.device, &gpu.queue, &self.render_texture, size);
+            let saved = capture::save_screenshot(
+                SCREENSHOT_DIR,
+                size,
+                &pixels,
+                &self.compute_uniforms,
+                samples,
+            );
+            self.report_screenshot(saved);
+            return;
+        }
+
+        let size = (size.0 * scale, size.1 * scale);
+        let texture = create_output_texture(&gpu.device, size);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let target = self
+            .raymarch_pipeline
+            .create_target(&gpu.device, &view, size);
+        self.screenshot = Some(PendingScreenshot {
+            texture,
+            target,
+            // as many samples as were accumulated on screen, one per frame so the
+            // application stays responsive
+            samples: samples.min(SCREENSHOT_MAX_SAMPLES),
+        });
+    }
+
+    /// Add a sample to the pending supersampled screenshot, and save it once complete.
+    fn render_screenshot(&mut self, gpu: &Gpu) {
+        let screenshot = match &mut self.screenshot {
+            Some(screenshot) => screenshot,
+            None => return,
+        };
+        self.raymarch_pipeline
+            .execute_target(&gpu.device, &gpu.queue, &mut screenshot.target);
+        if screenshot.target.sample_count() < screenshot.samples {
+            return;
+        }
+
+        let size = screenshot.target.resolution();
+        let pixels = read_texture(&gpu.device, &gpu.queue, &screenshot.texture, size);
+        let saved = capture::save_screenshot(
+            SCREENSHOT_DIR,
+            size,
+            &pixels,
+            screenshot.target.uniforms(),
+            screenshot.samples,
+        );
+        self.screenshot = None;
+        self.report_screenshot(saved);
+    }
+
+    fn report_screenshot(&mut self, saved: std::io::Result<std::path::PathBuf>) {
+        match saved {
+            Ok(path) => {
+                println!("screenshot saved to {}", path.display());
+                self.screenshot_status = Some(format!("saved {}", path.display()));
+            }
+            Err(e) => {
+                eprintln!("screenshot failed: {}", e);
+                self.screenshot_status = Some(format!("screenshot failed: {}", e));
+            }
+        }
+    }
+
+    /// Timeline of the camera track: keys are added at the current time from the current
+    /// view, the time slider scrubs through the animation.
+    fn camera_track_editor(&mut self, ui: &imgui::Ui) {
+        let session = ui.begin_disabled(self.session_active());
+        ui.checkbox("play", &mut self.play_camera_track);
+        session.end();
+        let end = self.camera_track.end().max(1.0);
+        imgui::Slider::new("time", 0.0, end).build(ui, &mut self.time);
+
+        let mut interpolation = Interpolation::ALL
+            .iter()
+            .position(|i| *i == self.camera_track.interpolation)
+            .unwrap();
+        let interpolation_names: Vec<_> = Interpolation::ALL.iter().map(|i| i.name()).collect();
+        if ui.combo_simple_string("interpolation", &mut interpolation, &interpolation_names) {
+            self.camera_track.interpolation = Interpolation::ALL[interpolation];
+        }
+
+        if ui.button("add key") {
+            self.camera_track
+                .insert_key(CameraKey::new(self.time, self.camera_controller.camera));
+        }
+
+        let ease_names: Vec<_> = Ease::ALL.iter().map(|e| e.name()).collect();
+        let mut edited = None;
+        let mut removed = None;
+        for (i, key) in self.camera_track.keys().iter().enumerate() {
+            // dragging the time reorders the keys, the widgets follow the key and not its
+            // index so the drag goes on
+            let id = ui.push_id(key.id() as i32);
+            ui.separator();
+
+            let mut key = *key;
+            let mut changed = imgui::Drag::new("time")
+                .speed(0.01)
+                .range(0.0, f32::MAX)
+                .build(ui, &mut key.time);
+            let mut ease = Ease::ALL.iter().position(|e| *e == key.ease).unwrap();
+            if ui.combo_simple_string("ease", &mut ease, &ease_names) {
+                key.ease = Ease::ALL[ease];
+                changed = true;
+            }
+            if ui.button("go to") {
+                self.time = key.time;
+            }
+            ui.same_line();
+            if ui.button("set view") {
+                key.camera = self.camera_controller.camera;
+                changed = true;
+            }
+            ui.same_line();
+            if ui.button("remove") {
+                removed = Some(i);
+            }
+            if changed {
+                edited = Some((i, key));
+            }
+
+            id.pop();
+        }
+        if let Some((i, key)) = edited {
+            self.camera_track.set_key(i, key);
+        }
+        if let Some(i) = removed {
+            self.camera_track.remove_key(i);
+        }
+
+        ui.separator();
+        ui.input_text("file", &mut self.camera_track_path).build();
+        if ui.button("save") {
+            self.camera_track_status =
+                Some(match self.camera_track.save(&self.camera_track_path) {
+                    Ok(()) => format!("saved {}", self.camera_track_path),
+                    Err(e) => format!("save failed: {}", e),
+                });
+        }
+        ui.same_line();
+        if ui.button("load") {
+            self.camera_track_status = Some(match CameraTrack::load(&self.camera_track_path) {
+                Ok(track) => {
+                    self.camera_track = track;
+                    format!("loaded {}", self.camera_track_path)
+                }
+                Err(e) => format!("load failed: {}", e),
+            });
+        }
+        if let Some(status) = &self.camera_track_status {
+            ui.text(status);
+        }
+    }
+
+    /// Recording and replay of the input, the camera track is not played meanwhile so the
+    /// camera only depends on the recorded events.
+    fn session_controls(&mut self, ui: &imgui::Ui) {
+        ui.input_text("session", &mut self.session_path).build();
+
+        if let Some(player) = &self.player {
+            ui.text(format!(
+                "replaying frame {}/{}",
+                player.position(),
+                player.session().frames.len()
+            ));
+            if ui.button("stop replay") {
+                self.player = None;
+                self.resize_pending = true;
+            }
+        } else if let Some(recorder) = &self.recorder {
+            ui.text(format!("recording, {} frames", recorder.frame_count()));
+            if ui.button("stop recording") {
+                self.session_status = self.save_recording().map(|result| match result {
+                    Ok(path) => format!("saved {}", path),
+                    Err(e) => format!("save failed: {}", e),
+                });
+            }
+        } else {
+            if ui.button("record") {
+                self.play_camera_track = false;
+                self.recorder = Some(Recorder::new(
+                    self.scene_path.clone(),
+                    self.render_size(),
+                    self.camera_controller,
+                ));
+            }
+            ui.same_line();
+            if ui.button("replay") {
+                match Session::load(&self.session_path) {
+                    Ok(session) => {
+                        self.play_camera_track = false;
+                        self.camera_controller = session.controller;
+                        self.session_status = None;
+                        self.player = Some(Player::new(session));
+                        self.resize_pending = true;
+                    }
+                    Err(e) => self.session_status = Some(format!("load failed: {}", e)),
+                }
+            }
+        }
+
+        if let Some(status) = &self.session_status {
+            ui.text(status);
+        }
+    }
+
+    /// A session is recorded or replayed, the widgets moving the camera are disabled
+    /// since the recording only holds the input events.
+    fn session_active(&self) -> bool {
+        self.recorder.is_some() || self.player.is_some()
+    }
+
+    /// Stop the recording and save it to `session_path`, returns `None` when nothing was
+    /// recorded.
+    fn save_recording(&mut self) -> Option<std::io::Result<String>> {
+        let session = self.recorder.take()?.finish();
+        Some(
+            session
+                .save(&self.session_path)
+                .map(|()| self.session_path.clone()),
+        )
+    }
+
+    /// Reallocate the render texture and the bind groups using it.
+    fn resize_render_texture(&mut self, gpu: &Gpu) {
+        self.render_texture = create_output_texture(&gpu.device, self.render_size());
+        let view = self
+            .render_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.raymarch_pipeline
+            .set_output(&gpu.device, &view, self.render_size());
+        self.render_bind_group = create_render_bind_group(
+            &gpu.device,
+            &self.render_bind_group_layout,
+            &view,
+            &self.render_sampler,
+        );
+        self.resize_pending = false;
+    }
+}
+
+impl Drop for MainApp {
+    fn drop(&mut self) {
+        match self.save_recording() {
+            Some(Ok(path)) => println!("session saved to {}", path),
+            Some(Err(e)) => eprintln!("could not save the session: {}", e),
+            None => {}
+        }
+    }
+}
+
+/// Widgets editing the shader defines, returns whether they should be applied: a
+/// checkbox changed or a slider was released, so dragging does not compile every value.
+fn shader_defines_editor(ui: &imgui::Ui, defines: &mut ShaderDefines) -> bool {
+    let mut apply = false;
+
+    imgui::Slider::new("AA", 1, 4).build(ui, &mut defines.aa);
+    apply |= ui.is_item_deactivated_after_edit();
+    apply |= ui.checkbox("BACKGROUND_ENABLE", &mut defines.background);
+    apply |= ui.checkbox("SHADOW_ENABLED", &mut defines.shadows);
+    imgui::Slider::new("MAX_STEPS", 16, 1024).build(ui, &mut defines.max_steps);
+    apply |= ui.is_item_deactivated_after_edit();
+    imgui::Drag::new("MIN_HIT_DIST")
+        .speed(0.0001)
+        .range(0.00001, 0.1)
+        .build(ui, &mut defines.min_hit_dist);
+    apply |= ui.is_item_deactivated_after_edit();
+    imgui::Slider::new("MAX_DIST", 10.0, 1000.0).build(ui, &mut defines.max_dist);
+    apply |= ui.is_item_deactivated_after_edit();
+
+    if ui.button("reset") {
+        *defines = ShaderDefines::default();
+        apply = true;
+    }
+
+    apply
+}
+
+/// Widgets built from the annotations of the parameters of the current shader, returns
+/// whether a value changed. The values are saved once an edit is done.
+fn shader_params_editor(ui: &imgui::Ui, params: &mut ShaderParams) -> bool {
+    if params.params().is_empty() {
+        ui.text("the shader has no `Params` block");
+        return false;
+    }
+
+    let mut changed = false;
+    let mut save = false;
+
+    for param in params.params_mut() {
+        let name = param.name.as_str();
+        let components = param.components as usize;
+        let value = &mut param.value;
+        let float = param.scalar == Scalar::Float;
+
+        changed |= match param.widget {
+            Widget::Checkbox => {
+                let mut checked = value[0] != 0.0;
+                let clicked = ui.checkbox(name, &mut checked);
+                value[0] = checked as u32 as f32;
+                save |= clicked;
+                clicked
+            }
+            Widget::Color if float && components == 3 => {
+                let color: &mut [f32; 3] = (&mut value[..3]).try_into().unwrap();
+                imgui::ColorEdit::new(name, color).build(ui)
+            }
+            Widget::Color if float && components == 4 => {
+                imgui::ColorEdit::new(name, value).build(ui)
+            }
+            Widget::Slider { min, max } if float => {
+                imgui::Slider::new(name, min, max).build_array(ui, &mut value[..components])
+            }
+            Widget::Slider { min, max } => edit_integers(&mut value[..components], |v| {
+                imgui::Slider::new(name, min as i32, max as i32).build_array(ui, v)
+            }),
+            Widget::Drag { speed } if float => imgui::Drag::new(name)
+                .speed(speed)
+                .build_array(ui, &mut value[..components]),
+            Widget::Drag { speed } => edit_integers(&mut value[..components], |v| {
+                imgui::Drag::new(name).speed(speed).build_array(ui, v)
+            }),
+            // colors of another type
+            Widget::Color => imgui::Drag::new(name)
+                .speed(0.01)
+                .build_array(ui, &mut value[..components]),
+        };
+        save |= ui.is_item_deactivated_after_edit();
+    }
+
+    if ui.button("reset") {
+        params.reset();
+        changed = true;
+        save = true;
+    }
+
+    if save {
+        if let Err(e) = params.save() {
+            eprintln!("could not save {}: {}", params.path().display(), e);
+        }
     }
+
+    changed
+}
+
+/// Edit integer parameters, which are stored as floats.
+fn edit_integers(value: &mut [f32], edit: impl FnOnce(&mut [i32]) -> bool) -> bool {
+    let mut integers: Vec<i32> = value.iter().map(|v| v.round() as i32).collect();
+    let changed = edit(&mut integers);
+    for (v, i) in value.iter_mut().zip(integers) {
+        *v = i as f32;
+    }
+
+    changed
+}
+
+/// Damping and mouse button mapping of the camera controller.
+fn camera_controls(ui: &imgui::Ui, controller: &mut CameraController) {
+    imgui::Slider::new("damping", 0.0, 50.0).build(ui, &mut controller.damping);
+
+    let names: Vec<_> = MouseAction::ALL.iter().map(|a| a.name()).collect();
+    let ButtonMapping {
+        left,
+        middle,
+        right,
+    } = &mut controller.buttons;
+    for (label, action) in [
+        ("left button", left),
+        ("middle button", middle),
+        ("right button", right),
+    ] {
+        let mut index = MouseAction::ALL.iter().position(|a| a == action).unwrap();
+        if ui.combo_simple_string(label, &mut index, &names) {
+            *action = MouseAction::ALL[index];
+        }
+    }
+    ui.text("double click: target the surface under the cursor");
+}
+
+/// Widgets editing the light list, returns whether it changed.
+fn light_editor(ui: &imgui::Ui, lights: &mut Vec<SceneLight>) -> bool {
+    const KINDS: [LightKind; 3] = [LightKind::Point, LightKind::Directional, LightKind::Spot];
+
+    let mut changed = false;
+    let mut removed = None;
+
+    for (i, light) in lights.iter_mut().enumerate() {
+        let id = ui.push_id(i as i32);
+        ui.separator();
+
+        let mut kind = KINDS.iter().position(|k| *k == light.kind).unwrap();
+        if ui.combo_simple_string("kind", &mut kind, &["point", "directional", "spot"]) {
+            light.kind = KINDS[kind];
+            changed = true;
+        }
+        if light.kind != LightKind::Directional {
+            changed |= imgui::Drag::new("position")
+                .speed(0.05)
+                .build_array(ui, light.position.as_mut());
+        }
+        if light.kind != LightKind::Point {
+            changed |= imgui::Drag::new("direction")
+                .speed(0.01)
+                .build_array(ui, light.direction.as_mut());
+        }
+        changed |= imgui::ColorEdit::new("color", light.color.as_mut()).build(ui);
+        changed |= imgui::Slider::new("intensity", 0.0, 10.0).build(ui, &mut light.intensity);
+        changed |= imgui::Slider::new("shadow k", 1.0, 128.0).build(ui, &mut light.shadow_k);
+        if light.kind == LightKind::Spot {
+            changed |= imgui::Slider::new("spot angle", 0.01, std::f32::consts::FRAC_PI_2)
+                .build(ui, &mut light.spot_angle);
+            changed |=
+                imgui::Slider::new("spot softness", 0.0, 1.0).build(ui, &mut light.spot_softness);
+        }
+        if ui.button("remove") {
+            removed = Some(i);
+        }
+
+        id.pop();
+    }
+
+    if let Some(i) = removed {
+        lights.remove(i);
+        changed = true;
+    }
+
+    ui.separator();
+    if lights.len() < Light::MAX_COUNT && ui.button("add light") {
+        lights.push(SceneLight::point(glam::vec3(0.0, 5.0, 0.0)));
+        changed = true;
+    }
+
+    changed
 }
 
 fn main() {
-    AppBuilder::new()
-        .with_name("Ray marching")
-        .with_dimension(TEXTURE_WIDTH, TEXTURE_HEIGHT)
-        .with_resizable(true)
-        .build()
-        .run::<MainApp>()
+    let command = cli::parse(std::env::args().skip(1));
+    if let Ok(command) = &command {
+        let dir = command.assets_dir();
+        if let Some(dir) = &dir {
+            if !dir.join("shaders").is_dir() {
+                eprintln!("no shaders directory in {}", dir.display());
+                process::exit(1);
+            }
+        }
+        // before any shader is loaded
+        assets::set_dir(dir);
+    }
+
+    match command {
+        Ok(Command::View(opts)) => {
+            if let Some(Err(e)) = opts.scene.as_ref().map(Scene::load) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            if let Some(Err(e)) = opts.camera_track.map(CameraTrack::load) {
+                eprintln!("invalid camera track: {}", e);
+                process::exit(1);
+            }
+            match opts.replay.map(Session::load) {
+                Some(Err(e)) => {
+                    eprintln!("invalid session: {}", e);
+                    process::exit(1);
+                }
+                Some(Ok(session)) if opts.scene.is_none() => {
+                    if let Some(Err(e)) = session.scene.map(Scene::load) {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+                _ => {}
+            }
+
+            AppBuilder::new()
+                .with_name("Ray marching")
+                .with_dimension(WINDOW_WIDTH, WINDOW_HEIGHT)
+                .with_resizable(true)
+                .build()
+                .run::<MainApp>()
+        }
+        Ok(Command::Render(opts)) => {
+            if let Err(e) = headless::render(&opts) {
+                eprintln!("render failed: {}", e);
+                process::exit(1);
+            }
+        }
+        Ok(Command::Export(opts)) => {
+            if let Err(e) = headless::export(&opts) {
+                eprintln!("export failed: {}", e);
+                process::exit(1);
+            }
+        }
+        Ok(Command::Replay(opts)) => {
+            if let Err(e) = headless::replay(&opts) {
+                eprintln!("replay failed: {}", e);
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    }
 }
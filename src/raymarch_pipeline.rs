@@ -1,52 +1,749 @@
 use shaderc;
-use std::fs;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
 
 use crate::{
+    assets,
     filewatcher::*,
-    utils::{load_spirv_shader, ComputeUniforms},
+    reflection::{self, Resource, ShaderReflection},
+    scene::{default_lights, default_material_table, Scene, SceneError, SceneLight, SceneMaterial},
+    shader_graph::IncludeGraph,
+    shader_params::{
+        parse_annotations, reflect_params, ShaderParam, ShaderParams, PARAMS_BINDING,
+        PARAMS_MAX_SIZE,
+    },
+    utils::{ComputeUniforms, Light, Material},
     wgpu,
 };
-use wgpu_sandbox::prelude::{Gpu, Texture};
 
+pub const WORKGROUP_LOCAL_SIZE: (u32, u32) = (16, 16);
+
+/// Compute shader rendering the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RenderMode {
+    /// Sphere tracing with phong shading, `main.glsl`.
+    #[default]
+    RayMarch,
+    /// Progressive path tracing of the same scene, `pathtrace.glsl`.
+    PathTrace,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 2] = [RenderMode::RayMarch, RenderMode::PathTrace];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RenderMode::RayMarch => "ray march",
+            RenderMode::PathTrace => "path trace",
+        }
+    }
+
+    fn shader(self) -> &'static str {
+        match self {
+            RenderMode::RayMarch => "main.glsl",
+            RenderMode::PathTrace => "pathtrace.glsl",
+        }
+    }
+
+    fn shader_path(self) -> PathBuf {
+        assets::shader_path(self.shader())
+    }
+}
+
+/// Output of the ray marcher, the debug views replace the shading of `compute_color`
+/// without recompiling the shader. Mirrors the `SHADING_*` defines of `utils.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShadingMode {
+    #[default]
+    Lit,
+    /// Heatmap of the sphere tracing iterations.
+    Steps,
+    /// Hit distance, from white at the camera to black at `MAX_DIST`.
+    Depth,
+    Normals,
+    MaterialId,
+    /// Black where the ray missed, red where it ran out of steps.
+    Mask,
+}
+
+impl ShadingMode {
+    pub const ALL: [ShadingMode; 6] = [
+        ShadingMode::Lit,
+        ShadingMode::Steps,
+        ShadingMode::Depth,
+        ShadingMode::Normals,
+        ShadingMode::MaterialId,
+        ShadingMode::Mask,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShadingMode::Lit => "lit",
+            ShadingMode::Steps => "step count",
+            ShadingMode::Depth => "depth",
+            ShadingMode::Normals => "normals",
+            ShadingMode::MaterialId => "material id",
+            ShadingMode::Mask => "missed / max steps",
+        }
+    }
+}
+
+/// Settings of `raymarch.glsl` which are preprocessor defines, changing them compiles
+/// another variant of the shaders.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShaderDefines {
+    /// Rays per pixel side when not accumulating, 1 disables the antialiasing.
+    pub aa: u32,
+    /// Draw the axes.
+    pub background: bool,
+    pub shadows: bool,
+    pub max_steps: u32,
+    pub min_hit_dist: f32,
+    pub max_dist: f32,
+}
+
+impl Default for ShaderDefines {
+    // same values as `raymarch.glsl`, which the precompiled shaders are built with
+    fn default() -> Self {
+        Self {
+            aa: 4,
+            background: true,
+            shadows: true,
+            max_steps: 256,
+            min_hit_dist: 0.001,
+            max_dist: 100.0,
+        }
+    }
+}
+
+impl ShaderDefines {
+    /// Names and values of the macros.
+    pub fn macros(&self) -> [(&'static str, String); 6] {
+        [
+            ("AA", self.aa.to_string()),
+            ("BACKGROUND_ENABLE", (self.background as u32).to_string()),
+            ("SHADOW_ENABLED", (self.shadows as u32).to_string()),
+            ("MAX_STEPS", self.max_steps.to_string()),
+            // debug formatting keeps a decimal point, so the literals stay floats
+            ("MIN_HIT_DIST", format!("{:?}", self.min_hit_dist)),
+            ("MAX_DIST", format!("{:?}", self.max_dist)),
+        ]
+    }
+
+    /// Identifies the compiled variant.
+    fn key(&self) -> String {
+        self.macros()
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Error reported while building the compute shader, shown to the user instead of
+/// aborting so the last working pipeline keeps running.
+#[derive(Debug, Clone)]
+pub struct ShaderDiagnostic {
+    pub file: String,
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl ShaderDiagnostic {
+    fn new(file: &str, message: impl ToString) -> Self {
+        Self {
+            file: file.to_string(),
+            line: None,
+            message: message.to_string(),
+        }
+    }
+
+    /// Split a shaderc error into one diagnostic per `file:line: error: message` line.
+    fn from_shaderc(file: &str, error: shaderc::Error) -> Vec<Self> {
+        let log = match error {
+            shaderc::Error::CompilationError(_, log) => log,
+            error => return vec![Self::new(file, error)],
+        };
+
+        log.lines()
+            .filter(|l| !l.trim().is_empty() && !l.ends_with("generated."))
+            .map(|l| {
+                let mut parts = l.splitn(3, ':');
+                match (parts.next(), parts.next().map(str::parse), parts.next()) {
+                    (Some(file), Some(Ok(line)), Some(message)) => Self {
+                        file: file.to_string(),
+                        line: Some(line),
+                        message: message.trim().to_string(),
+                    },
+                    _ => Self::new(file, l.trim()),
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Compile options resolving includes from the shaders of `assets`, except `scene.glsl`
+/// which is replaced by `scene_source` when a scene file was loaded. The included
+/// files are pushed to `includes`.
+fn compile_options<'a>(
+    scene_source: Option<&'a str>,
+    defines: &ShaderDefines,
+    includes: &'a RefCell<Vec<PathBuf>>,
+) -> shaderc::CompileOptions<'a> {
+    let mut opts = shaderc::CompileOptions::new().unwrap();
+    for (name, value) in defines.macros() {
+        opts.add_macro_definition(name, Some(&value));
+    }
+    opts.set_include_callback(move |src, _, _, _| {
+        let path = assets::shader_path(src);
+        let content = match scene_source {
+            Some(scene) if src == "scene.glsl" => scene.to_string(),
+            _ => {
+                // missing files are recorded too, to rebuild once they are created
+                includes.borrow_mut().push(path.clone());
+                assets::read_shader(&path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?
+                    .into_owned()
+            }
+        };
+
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: path.display().to_string(),
+            content,
+        })
+    });
+    opts.set_optimization_level(shaderc::OptimizationLevel::Performance);
+
+    opts
+}
+
+/// Compile the compute shader at `path` into SPIR-V, the files it includes are
+/// appended to `includes` even if the compilation fails.
+fn compile_shader(
+    compiler: &shaderc::Compiler,
+    path: &Path,
+    scene_source: Option<&str>,
+    defines: &ShaderDefines,
+    includes: &mut Vec<PathBuf>,
+) -> Result<shaderc::CompilationArtifact, Vec<ShaderDiagnostic>> {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let source = assets::read_shader(path)
+        .map_err(|e| vec![ShaderDiagnostic::new(&path.display().to_string(), e)])?;
+
+    let included = RefCell::new(Vec::new());
+    let binary_output = compiler.compile_into_spirv(
+        &source,
+        shaderc::ShaderKind::Compute,
+        name,
+        "main",
+        Some(&compile_options(scene_source, defines, &included)),
+    );
+    includes.extend(included.into_inner());
+
+    binary_output.map_err(|e| ShaderDiagnostic::from_shaderc(name, e))
+}
+
+/// Files included by the shader at `path`, found by running the preprocessor only.
+fn find_includes(
+    compiler: &shaderc::Compiler,
+    path: &Path,
+    scene_source: Option<&str>,
+) -> Vec<PathBuf> {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let source = match assets::read_shader(path) {
+        Ok(source) => source,
+        Err(_) => return Vec::new(),
+    };
+
+    let included = RefCell::new(Vec::new());
+    // errors are reported by the next compilation
+    let _ = compiler.preprocess(
+        &source,
+        name,
+        "main",
+        Some(&compile_options(
+            scene_source,
+            &ShaderDefines::default(),
+            &included,
+        )),
+    );
+
+    included.into_inner()
+}
+
+/// Create the compute pipeline, catching the validation errors which would otherwise
+/// abort the application.
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    mode: RenderMode,
+    spirv: &[u32],
+) -> Result<wgpu::ComputePipeline, ShaderDiagnostic> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+    let shader_module = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some(mode.shader()),
+        source: wgpu::ShaderSource::SpirV(Cow::Borrowed(spirv)),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(mode.shader()),
+        module: &shader_module,
+        entry_point: "main",
+        layout: Some(layout),
+    });
+
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(ShaderDiagnostic::new(mode.shader(), error)),
+        None => Ok(pipeline),
+    }
+}
+
+/// Differences between the resources of a shader and the bind group layout, or the
+/// Rust structs uploaded to its buffers.
+fn check_layout(reflection: &ShaderReflection) -> Vec<String> {
+    let mut errors = reflection::check_bindings(reflection, &bind_group_layout_entries());
+
+    for binding in reflection.bindings.iter().filter(|b| b.set == 0) {
+        match (binding.binding, &binding.resource) {
+            (1, Resource::UniformBuffer(block)) => errors.extend(reflection::check_struct(
+                block,
+                "ComputeUniforms",
+                &ComputeUniforms::fields(),
+                mem::size_of::<ComputeUniforms>(),
+            )),
+            (2, Resource::StorageBuffer { block, .. }) => errors.extend(reflection::check_array(
+                block,
+                0,
+                0,
+                "Material",
+                &Material::fields(),
+                mem::size_of::<Material>(),
+            )),
+            (3, Resource::StorageBuffer { block, .. }) => errors.extend(reflection::check_array(
+                block,
+                1,
+                Light::HEADER_SIZE,
+                "Light",
+                &Light::fields(),
+                mem::size_of::<Light>(),
+            )),
+            _ => {}
+        }
+    }
+
+    errors
+}
+
+/// Check a build of the shader at `path` against the bind group layout, before wgpu
+/// rejects it, and lay out its parameters with the annotations of its sources.
+fn reflect_shader(
+    path: &Path,
+    includes: &[PathBuf],
+    spirv: &[u32],
+) -> Result<Vec<ShaderParam>, Vec<ShaderDiagnostic>> {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let reflection =
+        reflection::reflect(spirv).map_err(|e| vec![ShaderDiagnostic::new(name, e)])?;
+
+    let errors = check_layout(&reflection);
+    if !errors.is_empty() {
+        return Err(errors
+            .into_iter()
+            .map(|e| ShaderDiagnostic::new(name, e))
+            .collect());
+    }
+
+    let mut annotations = HashMap::new();
+    for file in std::iter::once(path).chain(includes.iter().map(PathBuf::as_path)) {
+        if let Ok(source) = assets::read_shader(file) {
+            annotations.extend(parse_annotations(&file.display().to_string(), &source));
+        }
+    }
+
+    reflect_params(&reflection, &annotations).map_err(|e| vec![ShaderDiagnostic::new(name, e)])
+}
+
+/// Files watched for hot reload, the embedded shaders can't change.
+fn watched_files(includes: &IncludeGraph) -> Vec<PathBuf> {
+    match assets::dir() {
+        Some(_) => includes.files(),
+        None => Vec::new(),
+    }
+}
+
+fn join_diagnostics(diagnostics: &[ShaderDiagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(ShaderDiagnostic::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `Rgba32Float` texture holding the running average of the accumulated samples.
+fn create_accumulation_texture(device: &wgpu::Device, size: (u32, u32)) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("accumulation_texture"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+    })
+}
+
+/// Resources of set 0, declared by `raymarch.glsl` and the `Params` block.
+fn bind_group_layout_entries() -> [wgpu::BindGroupLayoutEntry; 7] {
+    [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                access: wgpu::StorageTextureAccess::WriteOnly,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                min_binding_size: None,
+                has_dynamic_offset: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                min_binding_size: None,
+                has_dynamic_offset: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 3,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                min_binding_size: None,
+                has_dynamic_offset: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 5,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                format: wgpu::TextureFormat::Rgba32Float,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                access: wgpu::StorageTextureAccess::WriteOnly,
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: PARAMS_BINDING,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                min_binding_size: None,
+                has_dynamic_offset: false,
+            },
+            count: None,
+        },
+    ]
+}
+
+/// Bind groups of the output texture, of the uniforms, materials, lights and parameter
+/// buffers, and of the accumulation textures. Read-write storage textures are not portable, so
+/// the two accumulation textures are swapped between the bind groups: the first one
+/// reads the average from `accumulation[0]` and writes it to `accumulation[1]`.
+fn create_bind_groups(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    output_view: &wgpu::TextureView,
+    accumulation: &[wgpu::Texture; 2],
+    buffers: [&wgpu::Buffer; 4],
+) -> [wgpu::BindGroup; 2] {
+    let accumulation_views =
+        [0, 1].map(|i| accumulation[i].create_view(&wgpu::TextureViewDescriptor::default()));
+
+    [0, 1].map(|i| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("compute_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(output_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(buffers[0].as_entire_buffer_binding()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer(buffers[1].as_entire_buffer_binding()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Buffer(buffers[2].as_entire_buffer_binding()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&accumulation_views[i]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&accumulation_views[1 - i]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: PARAMS_BINDING,
+                    resource: wgpu::BindingResource::Buffer(buffers[3].as_entire_buffer_binding()),
+                },
+            ],
+        })
+    })
+}
+
+/// Image rendered besides the output of the pipeline, with its own accumulation and the
+/// uniforms of the moment it was created, see [`RayMarchPipeline::execute_target`].
 #[derive(Debug)]
-pub struct RayMarchPipeline<'a> {
-    pipeline_layout: wgpu::PipelineLayout,
+pub struct RenderTarget {
+    bind_groups: [wgpu::BindGroup; 2],
+    // bound by `bind_groups`
+    _accumulation: [wgpu::Texture; 2],
+    resolution: (u32, u32),
+    uniforms: ComputeUniforms,
+    sample_count: u32,
+}
+
+impl RenderTarget {
+    pub fn resolution(&self) -> (u32, u32) {
+        self.resolution
+    }
+
+    pub fn uniforms(&self) -> &ComputeUniforms {
+        &self.uniforms
+    }
+
+    /// Number of samples averaged in the target.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+}
+
+/// Build of a shader requested from the compile thread.
+#[derive(Debug)]
+struct CompileJob {
+    mode: RenderMode,
+    // identifies the request, results of older requests are dropped
+    generation: u64,
+    scene_source: Option<String>,
+    defines: ShaderDefines,
+}
+
+impl CompileJob {
+    fn run(self, compiler: &shaderc::Compiler) -> CompileResult {
+        let path = self.mode.shader_path();
+        let mut includes = Vec::new();
+        let output = compile_shader(
+            compiler,
+            &path,
+            self.scene_source.as_deref(),
+            &self.defines,
+            &mut includes,
+        )
+        .map(|binary_output| binary_output.as_binary().to_vec());
+
+        CompileResult {
+            mode: self.mode,
+            generation: self.generation,
+            defines: self.defines,
+            includes,
+            output,
+        }
+    }
+}
+
+/// SPIR-V of a build, or its errors.
+#[derive(Debug)]
+struct CompileResult {
+    mode: RenderMode,
+    generation: u64,
+    defines: ShaderDefines,
+    includes: Vec<PathBuf>,
+    output: Result<Vec<u32>, Vec<ShaderDiagnostic>>,
+}
+
+/// Thread running shaderc, so the previous pipelines keep rendering while the shaders
+/// compile. The builds are checked and the pipelines created when the results are
+/// received, the device is owned by the render thread.
+#[derive(Debug)]
+struct CompileWorker {
+    jobs: Sender<CompileJob>,
+    results: Receiver<CompileResult>,
+}
+
+impl CompileWorker {
+    fn new() -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<CompileJob>();
+        let (result_sender, results) = mpsc::channel();
+
+        // stops once the pipeline, and the sender of the jobs, is dropped
+        thread::Builder::new()
+            .name("shader_compiler".to_string())
+            .spawn(move || {
+                let compiler = shaderc::Compiler::new().unwrap();
+                while let Ok(job) = job_receiver.recv() {
+                    // several saves in a row only need the last build of each shader
+                    let mut pending: Vec<CompileJob> = Vec::new();
+                    for job in std::iter::once(job).chain(job_receiver.try_iter()) {
+                        pending.retain(|j| j.mode != job.mode);
+                        pending.push(job);
+                    }
+
+                    for job in pending {
+                        if result_sender.send(job.run(&compiler)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .unwrap();
+
+        Self { jobs, results }
+    }
+}
+
+/// A build of a shader, along with the layout of its parameters.
+#[derive(Debug)]
+struct ShaderVariant {
     pipeline: wgpu::ComputePipeline,
-    bind_group: wgpu::BindGroup,
+    params: Vec<ShaderParam>,
+}
+
+impl ShaderVariant {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        mode: RenderMode,
+        includes: &[PathBuf],
+        spirv: &[u32],
+    ) -> Result<Self, Vec<ShaderDiagnostic>> {
+        // checked first, a mismatch would abort in wgpu with a less helpful message
+        let params = reflect_shader(&mode.shader_path(), includes, spirv)?;
+        let pipeline = create_pipeline(device, layout, mode, spirv).map_err(|e| vec![e])?;
+
+        Ok(Self { pipeline, params })
+    }
+}
+
+#[derive(Debug)]
+pub struct RayMarchPipeline {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    // compiled variants by `RenderMode` and `ShaderDefines::key`, flipping a define back
+    // reuses them
+    pipelines: Vec<HashMap<String, ShaderVariant>>,
+    // key of the variant running for each mode, the last one which built
+    active_variants: Vec<String>,
+    // cached variants built from sources which changed since, rebuilt when switched to
+    stale_variants: Vec<HashSet<String>>,
+    defines: ShaderDefines,
+    bind_groups: [wgpu::BindGroup; 2],
+    accumulation: [wgpu::Texture; 2],
     uniforms_buffer: wgpu::Buffer,
-    shader_observer: FileWatcher<'a>,
-    compiler: shaderc::Compiler,
+    materials_buffer: wgpu::Buffer,
+    lights_buffer: wgpu::Buffer,
+    params_buffer: wgpu::Buffer,
+    // parameters of the active variant of each mode
+    params: Vec<ShaderParams>,
+    shader_observer: FileWatcher,
+    shader_includes: IncludeGraph,
+    worker: CompileWorker,
+    // request of the build running for each mode, `None` when there is none
+    builds: Vec<Option<u64>>,
+    next_build: u64,
+    scene_source: Option<String>,
+    shader_errors: Vec<Vec<ShaderDiagnostic>>,
+    uniforms: ComputeUniforms,
+    mode: RenderMode,
+    accumulate: bool,
+    sample_count: u32,
 }
 
-impl<'a> RayMarchPipeline<'a> {
-    pub fn new(device: &wgpu::Device, output_texture: &Texture) -> Self {
+impl RayMarchPipeline {
+    /// Render into `output_view`, a texture of `resolution` pixels. Without `scene` the
+    /// default scene of `scene.glsl` is used. Shaders which fail to build are replaced by
+    /// the embedded ones, and their errors are kept in `shader_errors`.
+    pub fn new(
+        device: &wgpu::Device,
+        output_view: &wgpu::TextureView,
+        resolution: (u32, u32),
+        scene: Option<&Scene>,
+    ) -> Self {
+        let scene_source = scene.map(|s| s.to_glsl().expect("invalid scene"));
+
         let uniforms = ComputeUniforms::default();
         let uniforms_buffer = uniforms.build_buffer(device);
 
+        let materials: Vec<Material> = scene
+            .map_or_else(default_material_table, Scene::material_table)
+            .iter()
+            .map(Material::from)
+            .collect();
+        let materials_buffer = Material::build_buffer(&materials, device);
+
+        let lights: Vec<Light> = scene
+            .map_or_else(default_lights, |s| s.lights.clone())
+            .iter()
+            .map(Light::from)
+            .collect();
+        let lights_buffer = Light::build_buffer(&lights, device);
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params_buffer"),
+            size: PARAMS_MAX_SIZE as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("compute_bind_group_layout"),
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        access: wgpu::StorageTextureAccess::WriteOnly,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        min_binding_size: None,
-                        has_dynamic_offset: false,
-                    },
-                    count: None,
-                },
-            ],
+            entries: &bind_group_layout_entries(),
         });
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -55,119 +752,520 @@ impl<'a> RayMarchPipeline<'a> {
             push_constant_ranges: &[],
         });
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("compute_bind_group"),
-            layout: &bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&output_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Buffer(
-                        uniforms_buffer.as_entire_buffer_binding(),
-                    ),
-                },
+        let accumulation = [0, 1].map(|_| create_accumulation_texture(device, resolution));
+        let bind_groups = create_bind_groups(
+            device,
+            &bind_group_layout,
+            output_view,
+            &accumulation,
+            [
+                &uniforms_buffer,
+                &materials_buffer,
+                &lights_buffer,
+                &params_buffer,
             ],
-        });
+        );
 
-        let shader_mod =
-            load_spirv_shader("./assets/compiled_shaders/main.glsl.spv", device).unwrap();
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("main_compute_pipeline"),
-            module: &shader_mod,
-            entry_point: "main",
-            layout: Some(&pipeline_layout),
-        });
+        let compiler = shaderc::Compiler::new().unwrap();
 
-        let shader_observer = FileWatcher::new(&[
-            "./assets/shaders/main.glsl",
-            "./assets/shaders/sdf.glsl",
-            "./assets/shaders/utils.glsl",
-        ])
-        .unwrap();
+        let defines = ShaderDefines::default();
+        let mut shader_includes = IncludeGraph::new();
+        let mut pipelines = Vec::new();
+        let mut stale_variants = Vec::new();
+        let mut params = Vec::new();
+        let mut shader_errors = Vec::new();
+        for mode in RenderMode::ALL {
+            let path = mode.shader_path();
+            let mut includes = Vec::new();
+            let mut errors = Vec::new();
+            // the shaders of an assets directory may have changed since they were embedded
+            let compiled = if scene_source.is_some() || assets::dir().is_some() {
+                let variant = compile_shader(
+                    &compiler,
+                    &path,
+                    scene_source.as_deref(),
+                    &defines,
+                    &mut includes,
+                )
+                .and_then(|binary_output| {
+                    ShaderVariant::new(
+                        device,
+                        &pipeline_layout,
+                        mode,
+                        &includes,
+                        binary_output.as_binary(),
+                    )
+                });
+                match variant {
+                    Ok(variant) => Some(variant),
+                    Err(e) => {
+                        errors = e;
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            // the embedded shader runs until the errors are fixed, they are reported by the
+            // ui and the next save rebuilds the shader
+            let variant = compiled.unwrap_or_else(|| {
+                includes = find_includes(&compiler, &path, scene_source.as_deref());
+                let spirv = wgpu::util::make_spirv_raw(assets::compiled_shader(mode.shader()));
+                ShaderVariant::new(device, &pipeline_layout, mode, &includes, &spirv)
+                    .unwrap_or_else(|errors| panic!("{}", join_diagnostics(&errors)))
+            });
+            if !errors.is_empty() {
+                eprintln!("{}", join_diagnostics(&errors));
+            }
 
-        let compiler = shaderc::Compiler::new().unwrap();
+            let mut mode_params = ShaderParams::new(mode.shader());
+            mode_params.set_layout(variant.params.clone());
+            params.push(mode_params);
+
+            shader_includes.set_includes(&path, includes);
+            pipelines.push(HashMap::from([(defines.key(), variant)]));
+            stale_variants.push(match errors.is_empty() {
+                true => HashSet::new(),
+                false => HashSet::from([defines.key()]),
+            });
+            shader_errors.push(errors);
+        }
+        let shader_observer = FileWatcher::new(&watched_files(&shader_includes));
 
         Self {
+            bind_group_layout,
             pipeline_layout,
             shader_observer,
-            pipeline,
-            bind_group,
+            shader_includes,
+            pipelines,
+            active_variants: vec![defines.key(); RenderMode::ALL.len()],
+            stale_variants,
+            defines,
+            bind_groups,
+            accumulation,
             uniforms_buffer,
-            compiler,
+            materials_buffer,
+            lights_buffer,
+            params_buffer,
+            params,
+            worker: CompileWorker::new(),
+            builds: vec![None; RenderMode::ALL.len()],
+            next_build: 0,
+            scene_source,
+            shader_errors,
+            uniforms,
+            mode: RenderMode::default(),
+            accumulate: false,
+            sample_count: 0,
         }
     }
 
-    pub fn upload_uniforms(&self, gpu: &Gpu, uniforms: &ComputeUniforms) {
-        uniforms.update_buffer(&self.uniforms_buffer, &gpu)
+    /// Render into a new texture of `resolution` pixels, e.g. after the window was resized.
+    pub fn set_output(
+        &mut self,
+        device: &wgpu::Device,
+        output_view: &wgpu::TextureView,
+        resolution: (u32, u32),
+    ) {
+        self.accumulation = [0, 1].map(|_| create_accumulation_texture(device, resolution));
+        self.bind_groups = create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            output_view,
+            &self.accumulation,
+            [
+                &self.uniforms_buffer,
+                &self.materials_buffer,
+                &self.lights_buffer,
+                &self.params_buffer,
+            ],
+        );
+        self.reset_accumulation();
     }
 
-    pub fn update_shader(&mut self, gpu: &Gpu) {
-        let modified_shaders = self.shader_observer.modified();
-        if modified_shaders.len() != 0 {
-            let mut opts = shaderc::CompileOptions::new().unwrap();
-            opts.set_include_callback(|src, _, _, _| {
-                Ok(shaderc::ResolvedInclude {
-                    resolved_name: format!("./assets/shaders/{}", src),
-                    content: fs::read_to_string(format!("./assets/shaders/{}", src)).unwrap(),
-                })
-            });
-            opts.set_optimization_level(shaderc::OptimizationLevel::Performance);
-
-            for shader_path in modified_shaders {
-                let shader_name = shader_path.file_name().unwrap().to_str().unwrap();
-
-                let binary_output = self
-                    .compiler
-                    .compile_into_spirv(
-                        fs::read_to_string(shader_path).unwrap().as_str(),
-                        shaderc::ShaderKind::Compute,
-                        shader_name,
-                        "main",
-                        Some(&opts),
-                    )
-                    .unwrap();
+    /// Target rendering into `output_view`, a texture of `resolution` pixels, with the
+    /// current uniforms. The output and accumulation of the pipeline are left untouched.
+    pub fn create_target(
+        &self,
+        device: &wgpu::Device,
+        output_view: &wgpu::TextureView,
+        resolution: (u32, u32),
+    ) -> RenderTarget {
+        let accumulation = [0, 1].map(|_| create_accumulation_texture(device, resolution));
+        let bind_groups = create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            output_view,
+            &accumulation,
+            [
+                &self.uniforms_buffer,
+                &self.materials_buffer,
+                &self.lights_buffer,
+                &self.params_buffer,
+            ],
+        );
+
+        RenderTarget {
+            bind_groups,
+            _accumulation: accumulation,
+            resolution,
+            uniforms: self.uniforms,
+            sample_count: 0,
+        }
+    }
+
+    /// Replace the scene rendered by the pipeline, along with its materials and lights.
+    /// The shaders of the new scene run once `receive_shaders` got them.
+    pub fn set_scene(&mut self, queue: &wgpu::Queue, scene: &Scene) -> Result<(), SceneError> {
+        self.scene_source = Some(scene.to_glsl()?);
+        for mode in RenderMode::ALL {
+            self.rebuild_shader(mode);
+        }
+        self.upload_materials(queue, &scene.material_table());
+        self.upload_lights(queue, &scene.lights);
+
+        Ok(())
+    }
+
+    /// Update the material table, it must not exceed `Material::MAX_COUNT` entries.
+    pub fn upload_materials(&mut self, queue: &wgpu::Queue, materials: &[SceneMaterial]) {
+        let materials: Vec<Material> = materials.iter().map(Material::from).collect();
+        Material::update_buffer(&materials, &self.materials_buffer, queue);
+        self.reset_accumulation();
+    }
+
+    /// Update the lights, there must not be more than `Light::MAX_COUNT` of them.
+    pub fn upload_lights(&mut self, queue: &wgpu::Queue, lights: &[SceneLight]) {
+        let lights: Vec<Light> = lights.iter().map(Light::from).collect();
+        Light::update_buffer(&lights, &self.lights_buffer, queue);
+        self.reset_accumulation();
+    }
+
+    /// Update the uniforms, the accumulated samples are dropped when they changed.
+    pub fn upload_uniforms(&mut self, queue: &wgpu::Queue, uniforms: &ComputeUniforms) {
+        // the sample index and the accumulation flag are owned by the pipeline
+        let mut previous = self.uniforms;
+        previous.sample_index = uniforms.sample_index;
+        previous.accumulate = uniforms.accumulate;
+        if bytemuck::bytes_of(&previous) != bytemuck::bytes_of(uniforms) {
+            self.reset_accumulation();
+        }
+
+        self.uniforms = *uniforms;
+        self.write_uniforms(queue);
+    }
+
+    fn write_uniforms(&self, queue: &wgpu::Queue) {
+        let mut uniforms = self.uniforms;
+        uniforms.sample_index = self.sample_count as i32;
+        uniforms.accumulate = self.accumulating() as i32;
+        uniforms.update_buffer(&self.uniforms_buffer, queue)
+    }
+
+    pub fn render_mode(&self) -> RenderMode {
+        self.mode
+    }
+
+    pub fn set_render_mode(&mut self, mode: RenderMode) {
+        if mode != self.mode {
+            self.mode = mode;
+            self.reset_accumulation();
+        }
+    }
+
+    /// Whether successive dispatches are averaged, the path tracer is always progressive.
+    pub fn accumulating(&self) -> bool {
+        self.accumulate || self.mode == RenderMode::PathTrace
+    }
+
+    /// Average the samples of successive dispatches as long as nothing changes, instead
+    /// of rendering each frame from scratch.
+    pub fn set_accumulation(&mut self, enabled: bool) {
+        if enabled != self.accumulate {
+            self.accumulate = enabled;
+            self.reset_accumulation();
+        }
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.sample_count = 0;
+    }
+
+    /// Number of samples averaged in the output.
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    pub fn defines(&self) -> ShaderDefines {
+        self.defines
+    }
+
+    /// Switch to the shader variants built with `defines`, the ones which are not in the
+    /// cache yet run once `receive_shaders` got them.
+    pub fn set_defines(&mut self, defines: ShaderDefines) {
+        if defines == self.defines {
+            return;
+        }
+        self.defines = defines;
+
+        let key = defines.key();
+        for mode in RenderMode::ALL {
+            let variant = match self.pipelines[mode as usize].get(&key) {
+                Some(variant) => variant,
+                None => {
+                    self.build_variant(mode);
+                    continue;
+                }
+            };
+            self.params[mode as usize].set_layout(variant.params.clone());
+            self.active_variants[mode as usize] = key.clone();
+            self.shader_errors[mode as usize].clear();
+            self.reset_accumulation();
+
+            if self.stale_variants[mode as usize].contains(&key) {
+                // runs until the build from the current sources replaces it
+                self.build_variant(mode);
+            } else {
+                // a build of other defines would replace it
+                self.builds[mode as usize] = None;
+            }
+        }
+    }
+
+    /// Errors of the last build of each shader, empty when they succeeded.
+    pub fn shader_errors(&self) -> Vec<&ShaderDiagnostic> {
+        self.shader_errors.iter().flatten().collect()
+    }
+
+    /// Parameters of the shader of the current mode.
+    pub fn shader_params(&self) -> &ShaderParams {
+        &self.params[self.mode as usize]
+    }
+
+    /// Edit the parameters of the shader of the current mode, the accumulation has to be
+    /// reset after a change.
+    pub fn shader_params_mut(&mut self) -> &mut ShaderParams {
+        &mut self.params[self.mode as usize]
+    }
+
+    /// A shader is being compiled, the previous one is running meanwhile.
+    pub fn is_compiling(&self) -> bool {
+        self.builds.iter().any(Option::is_some)
+    }
+
+    /// Rebuild the entry shaders depending on the modified files, in the background.
+    pub fn update_shader(&mut self) {
+        // removed files are rebuilt too, to report them as missing
+        let changed_files: Vec<PathBuf> = self
+            .shader_observer
+            .events()
+            .into_iter()
+            .map(FileEvent::into_path)
+            .collect();
+        let entries = self.shader_includes.dependents(&changed_files);
+        for mode in RenderMode::ALL {
+            if entries.contains(&mode.shader_path()) {
+                self.rebuild_shader(mode);
+            }
+        }
+    }
+
+    /// Recompile the shader of `mode` after its sources changed, the current pipeline is
+    /// kept if it fails.
+    fn rebuild_shader(&mut self, mode: RenderMode) {
+        // the other variants were built from the previous sources, the active one keeps
+        // running until it is rebuilt
+        let active = &self.active_variants[mode as usize];
+        self.pipelines[mode as usize].retain(|key, _| key == active);
+        self.stale_variants[mode as usize] = HashSet::from([active.clone()]);
+
+        self.build_variant(mode);
+    }
 
-                fs::write(
-                    format!("./assets/compiled_shaders/{}.spv", shader_name),
-                    binary_output.as_binary_u8(),
+    /// Compile the shader of `mode` with the current defines on the compile thread, a
+    /// build still running for the mode is superseded.
+    fn build_variant(&mut self, mode: RenderMode) {
+        let generation = self.next_build;
+        self.next_build += 1;
+        self.builds[mode as usize] = Some(generation);
+
+        self.worker
+            .jobs
+            .send(CompileJob {
+                mode,
+                generation,
+                scene_source: self.scene_source.clone(),
+                defines: self.defines,
+            })
+            .unwrap();
+    }
+
+    /// Swap in the shaders compiled since the last call, between two dispatches. A build
+    /// which failed keeps the current pipeline.
+    pub fn receive_shaders(&mut self, device: &wgpu::Device) {
+        while let Ok(result) = self.worker.results.try_recv() {
+            let mode = result.mode as usize;
+            // another build was requested meanwhile
+            if self.builds[mode] != Some(result.generation) {
+                continue;
+            }
+            self.builds[mode] = None;
+
+            let variant = result.output.and_then(|spirv| {
+                ShaderVariant::new(
+                    device,
+                    &self.pipeline_layout,
+                    result.mode,
+                    &result.includes,
+                    &spirv,
                 )
-                .unwrap()
+            });
+
+            // includes may have been added or removed
+            self.shader_includes
+                .set_includes(&result.mode.shader_path(), result.includes);
+            self.shader_observer
+                .set_files(&watched_files(&self.shader_includes));
+
+            match variant {
+                Ok(variant) => {
+                    let key = result.defines.key();
+                    self.params[mode].set_layout(variant.params.clone());
+                    self.stale_variants[mode].remove(&key);
+                    self.pipelines[mode].insert(key.clone(), variant);
+                    self.active_variants[mode] = key;
+                    self.shader_errors[mode].clear();
+                    self.reset_accumulation();
+                }
+                Err(errors) => self.shader_errors[mode] = errors,
             }
+        }
+    }
 
-            let shader_module =
-                load_spirv_shader("./assets/compiled_shaders/main.glsl.spv", &gpu.device).unwrap();
+    /// Number of workgroups covering an image of `resolution` pixels.
+    pub fn dispatch_size(resolution: (u32, u32)) -> (u32, u32) {
+        (
+            resolution.0.div_ceil(WORKGROUP_LOCAL_SIZE.0),
+            resolution.1.div_ceil(WORKGROUP_LOCAL_SIZE.1),
+        )
+    }
 
-            self.pipeline = gpu
-                .device
-                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    label: Some("main_compute_pipeline"),
-                    module: &shader_module,
-                    entry_point: "main",
-                    layout: Some(&self.pipeline_layout),
-                });
+    /// Ray march an image of `resolution` pixels into the output texture, adding one
+    /// sample to the average when accumulating.
+    pub fn execute(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, resolution: (u32, u32)) {
+        self.write_uniforms(queue);
+        queue.write_buffer(
+            &self.params_buffer,
+            0,
+            &self.params[self.mode as usize].to_bytes(),
+        );
+        let bind_group = &self.bind_groups[self.sample_count as usize % 2];
+        self.dispatch(device, queue, bind_group, resolution);
+
+        if self.accumulating() {
+            self.sample_count += 1;
         }
     }
 
-    pub fn execute(&self, gpu: &Gpu, workgroup_size: (u32, u32)) {
-        let mut compute_encoder =
-            gpu.device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("compute_encoder"),
-                });
+    /// Add one sample to `target`. The uniforms buffer is shared, the next call to
+    /// [`RayMarchPipeline::execute`] writes the ones of the pipeline back.
+    pub fn execute_target(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: &mut RenderTarget,
+    ) {
+        let mut uniforms = target.uniforms;
+        uniforms.sample_index = target.sample_count as i32;
+        uniforms.accumulate = self.accumulating() as i32;
+        uniforms.update_buffer(&self.uniforms_buffer, queue);
+        let bind_group = &target.bind_groups[target.sample_count as usize % 2];
+        self.dispatch(device, queue, bind_group, target.resolution);
+
+        target.sample_count += 1;
+    }
+
+    fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_group: &wgpu::BindGroup,
+        resolution: (u32, u32),
+    ) {
+        let workgroup_size = Self::dispatch_size(resolution);
+
+        let mut compute_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("compute_encoder"),
+        });
 
         {
             let mut cpass = compute_encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("main_compute_pass"),
             });
 
-            cpass.set_pipeline(&self.pipeline);
-            cpass.set_bind_group(0, &self.bind_group, &[]);
+            let mode = self.mode as usize;
+            cpass.set_pipeline(&self.pipelines[mode][&self.active_variants[mode]].pipeline);
+            cpass.set_bind_group(0, bind_group, &[]);
             cpass.dispatch(workgroup_size.0, workgroup_size.1, 1);
         }
 
-        gpu.queue.submit(std::iter::once(compute_encoder.finish()));
+        queue.submit(std::iter::once(compute_encoder.finish()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatch_size_covers_the_image() {
+        assert_eq!(RayMarchPipeline::dispatch_size((1366, 768)), (86, 48));
+        assert_eq!(RayMarchPipeline::dispatch_size((1, 1)), (1, 1));
+        assert_eq!(RayMarchPipeline::dispatch_size((17, 15)), (2, 1));
+        assert_eq!(
+            RayMarchPipeline::dispatch_size((
+                WORKGROUP_LOCAL_SIZE.0 * 80,
+                WORKGROUP_LOCAL_SIZE.1 * 45
+            )),
+            (80, 45)
+        );
+    }
+
+    /// Compile the shaders of every mode as the viewer does, their blocks have to match
+    /// the Rust structs uploaded to them.
+    #[test]
+    fn compiled_shaders_match_the_rust_layout() {
+        let compiler = shaderc::Compiler::new().unwrap();
+        for mode in RenderMode::ALL {
+            let path = mode.shader_path();
+            let mut includes = Vec::new();
+            let spirv = compile_shader(
+                &compiler,
+                &path,
+                None,
+                &ShaderDefines::default(),
+                &mut includes,
+            )
+            .unwrap_or_else(|errors| panic!("{}", join_diagnostics(&errors)));
+            let reflection = reflection::reflect(spirv.as_binary()).unwrap();
+            assert_eq!(
+                check_layout(&reflection),
+                Vec::<String>::new(),
+                "{}",
+                path.display()
+            );
+
+            let block = |binding| match reflection.binding(0, binding).map(|b| &b.resource) {
+                Some(Resource::UniformBuffer(block))
+                | Some(Resource::StorageBuffer { block, .. }) => Some(block.name.as_str()),
+                _ => None,
+            };
+            assert_eq!(block(1), Some("Uniforms"), "{}", path.display());
+            assert_eq!(block(2), Some("Materials"), "{}", path.display());
+            assert_eq!(block(3), Some("Lights"), "{}", path.display());
+            if mode == RenderMode::RayMarch {
+                assert_eq!(block(PARAMS_BINDING), Some("Params"), "{}", path.display());
+            }
+        }
     }
 }
@@ -0,0 +1,870 @@
+//! Reflection of the compiled shaders: the resources bound by a SPIR-V module and the
+//! layout of its blocks, read straight from the instructions.
+
+use std::collections::HashMap;
+
+use crate::wgpu;
+
+const MAGIC: u32 = 0x0723_0203;
+
+// opcodes
+const OP_NAME: u32 = 5;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// decorations
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_NON_WRITABLE: u32 = 24;
+const DECORATION_NON_READABLE: u32 = 25;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+// storage classes
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    Bool,
+    Int,
+    Uint,
+    Float,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Scalar(Scalar),
+    Vector(Scalar, u32),
+    /// Columns of `Vector` type.
+    Matrix(Box<Type>, u32),
+    /// `None` length for runtime arrays, the stride is only known in blocks.
+    Array {
+        element: Box<Type>,
+        length: Option<u32>,
+        stride: Option<u32>,
+    },
+    Struct(Struct),
+    Image(Image),
+    Sampler,
+    Unknown,
+}
+
+impl Type {
+    /// Component type of scalars, vectors and matrices.
+    pub fn scalar(&self) -> Option<Scalar> {
+        match self {
+            Type::Scalar(s) | Type::Vector(s, _) => Some(*s),
+            Type::Matrix(column, _) => column.scalar(),
+            _ => None,
+        }
+    }
+
+    /// Size of scalars and vectors, `None` for the other types.
+    pub fn size(&self) -> Option<u32> {
+        match self {
+            Type::Scalar(_) => Some(4),
+            Type::Vector(_, n) => Some(4 * n),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub name: String,
+    /// Byte offset in the block, `None` outside of blocks.
+    pub offset: Option<u32>,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Struct {
+    pub name: String,
+    pub members: Vec<Member>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Image {
+    /// `Dim` operand, 1 for 2D images.
+    pub dim: u32,
+    /// 1 when sampled, 2 for storage images.
+    pub sampled: u32,
+    /// `ImageFormat` operand, 0 when unknown.
+    pub format: u32,
+}
+
+/// Kind of a descriptor, as declared by the shader.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resource {
+    UniformBuffer(Struct),
+    StorageBuffer {
+        block: Struct,
+        read_only: bool,
+    },
+    SampledImage(Image),
+    StorageImage {
+        image: Image,
+        write_only: bool,
+        read_only: bool,
+    },
+    Sampler,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub set: u32,
+    pub binding: u32,
+    /// Name of the variable, empty for anonymous blocks.
+    pub name: String,
+    pub resource: Resource,
+}
+
+/// Resources of a module, sorted by set and binding.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderReflection {
+    pub bindings: Vec<Binding>,
+}
+
+impl ShaderReflection {
+    pub fn binding(&self, set: u32, binding: u32) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|b| b.set == set && b.binding == binding)
+    }
+}
+
+/// Instructions of the module needed to resolve the types of the variables.
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    flags: HashMap<u32, Vec<u32>>,
+    constants: HashMap<u32, u32>,
+    types: HashMap<u32, Vec<u32>>,
+    pointers: HashMap<u32, (u32, u32)>,
+    // id, pointer type and storage class of the global variables
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn has_flag(&self, id: u32, decoration: u32) -> bool {
+        self.flags.get(&id).is_some_and(|f| f.contains(&decoration))
+    }
+
+    fn resolve(&self, id: u32) -> Type {
+        let words = match self.types.get(&id) {
+            Some(words) => words,
+            None => return Type::Unknown,
+        };
+        let scalar = |id: u32| match self.resolve(id) {
+            Type::Scalar(s) => s,
+            _ => Scalar::Float,
+        };
+
+        match (words[0] & 0xffff, &words[1..]) {
+            (OP_TYPE_BOOL, _) => Type::Scalar(Scalar::Bool),
+            (OP_TYPE_INT, [_, _, signed, ..]) if *signed == 1 => Type::Scalar(Scalar::Int),
+            (OP_TYPE_INT, _) => Type::Scalar(Scalar::Uint),
+            (OP_TYPE_FLOAT, _) => Type::Scalar(Scalar::Float),
+            (OP_TYPE_VECTOR, [_, component, count, ..]) => Type::Vector(scalar(*component), *count),
+            (OP_TYPE_MATRIX, [_, column, count, ..]) => {
+                Type::Matrix(Box::new(self.resolve(*column)), *count)
+            }
+            (OP_TYPE_IMAGE, [_, _, dim, _, _, _, sampled, format, ..]) => Type::Image(Image {
+                dim: *dim,
+                sampled: *sampled,
+                format: *format,
+            }),
+            (OP_TYPE_SAMPLER, _) => Type::Sampler,
+            (OP_TYPE_ARRAY, [_, element, length, ..]) => Type::Array {
+                element: Box::new(self.resolve(*element)),
+                length: self.constants.get(length).copied(),
+                stride: self
+                    .decorations
+                    .get(&(id, DECORATION_ARRAY_STRIDE))
+                    .copied(),
+            },
+            (OP_TYPE_RUNTIME_ARRAY, [_, element, ..]) => Type::Array {
+                element: Box::new(self.resolve(*element)),
+                length: None,
+                stride: self
+                    .decorations
+                    .get(&(id, DECORATION_ARRAY_STRIDE))
+                    .copied(),
+            },
+            (OP_TYPE_STRUCT, [_, members @ ..]) => Type::Struct(Struct {
+                name: self.names.get(&id).cloned().unwrap_or_default(),
+                members: members
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ty)| Member {
+                        name: self
+                            .member_names
+                            .get(&(id, i as u32))
+                            .cloned()
+                            .unwrap_or_default(),
+                        offset: self.member_offsets.get(&(id, i as u32)).copied(),
+                        ty: self.resolve(*ty),
+                    })
+                    .collect(),
+            }),
+            _ => Type::Unknown,
+        }
+    }
+}
+
+/// Read a nul terminated string packed in `words`.
+fn string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .take_while(|b| *b != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Descriptors declared by the SPIR-V module `spirv`.
+pub fn reflect(spirv: &[u32]) -> Result<ShaderReflection, String> {
+    if spirv.len() < 5 || spirv[0] != MAGIC {
+        return Err("not a SPIR-V module".to_string());
+    }
+
+    let mut module = Module::default();
+    let mut words = &spirv[5..];
+    while !words.is_empty() {
+        let count = (words[0] >> 16) as usize;
+        if count == 0 || count > words.len() {
+            return Err("truncated SPIR-V module".to_string());
+        }
+        let (instruction, rest) = words.split_at(count);
+        words = rest;
+
+        match (instruction[0] & 0xffff, &instruction[1..]) {
+            (OP_NAME, [id, name @ ..]) => {
+                module.names.insert(*id, string(name));
+            }
+            (OP_MEMBER_NAME, [id, member, name @ ..]) => {
+                module.member_names.insert((*id, *member), string(name));
+            }
+            (OP_DECORATE, [id, decoration, value, ..]) => {
+                module.decorations.insert((*id, *decoration), *value);
+            }
+            (OP_DECORATE, [id, decoration]) => {
+                module.flags.entry(*id).or_default().push(*decoration);
+            }
+            (OP_MEMBER_DECORATE, [id, member, DECORATION_OFFSET, offset, ..]) => {
+                module.member_offsets.insert((*id, *member), *offset);
+            }
+            (OP_MEMBER_DECORATE, [id, _, decoration]) => {
+                // a flag on any member applies to the block in our shaders, e.g. the
+                // `readonly` qualifier of a buffer
+                module.flags.entry(*id).or_default().push(*decoration);
+            }
+            (OP_CONSTANT, [_, id, value, ..]) => {
+                module.constants.insert(*id, *value);
+            }
+            (OP_TYPE_POINTER, [id, storage, ty, ..]) => {
+                module.pointers.insert(*id, (*storage, *ty));
+            }
+            (OP_VARIABLE, [ty, id, storage, ..]) => {
+                module.variables.push((*id, *ty, *storage));
+            }
+            (op, [id, ..]) if (OP_TYPE_BOOL..=OP_TYPE_STRUCT).contains(&op) => {
+                module.types.insert(*id, instruction.to_vec());
+            }
+            _ => {}
+        }
+    }
+
+    let mut bindings = Vec::new();
+    for &(id, pointer, storage) in &module.variables {
+        let (set, binding) = match (
+            module.decorations.get(&(id, DECORATION_DESCRIPTOR_SET)),
+            module.decorations.get(&(id, DECORATION_BINDING)),
+        ) {
+            (Some(set), Some(binding)) => (*set, *binding),
+            _ => continue,
+        };
+        let ty_id = module.pointers.get(&pointer).map_or(0, |p| p.1);
+        let non_writable = module.has_flag(id, DECORATION_NON_WRITABLE)
+            || module.has_flag(ty_id, DECORATION_NON_WRITABLE);
+
+        let resource = match (storage, module.resolve(ty_id)) {
+            // storage buffers are `Uniform` blocks decorated with `BufferBlock` before
+            // SPIR-V 1.3
+            (STORAGE_UNIFORM, Type::Struct(block))
+                if module.has_flag(ty_id, DECORATION_BUFFER_BLOCK) =>
+            {
+                Resource::StorageBuffer {
+                    block,
+                    read_only: non_writable,
+                }
+            }
+            (STORAGE_UNIFORM, Type::Struct(block)) => Resource::UniformBuffer(block),
+            (STORAGE_STORAGE_BUFFER, Type::Struct(block)) => Resource::StorageBuffer {
+                block,
+                read_only: non_writable,
+            },
+            (STORAGE_UNIFORM_CONSTANT, Type::Image(image)) if image.sampled == 2 => {
+                Resource::StorageImage {
+                    image,
+                    write_only: module.has_flag(id, DECORATION_NON_READABLE),
+                    read_only: non_writable,
+                }
+            }
+            (STORAGE_UNIFORM_CONSTANT, Type::Image(image)) => Resource::SampledImage(image),
+            (STORAGE_UNIFORM_CONSTANT, Type::Sampler) => Resource::Sampler,
+            _ => Resource::Other,
+        };
+
+        bindings.push(Binding {
+            set,
+            binding,
+            name: module.names.get(&id).cloned().unwrap_or_default(),
+            resource,
+        });
+    }
+    bindings.sort_by_key(|b| (b.set, b.binding));
+
+    Ok(ShaderReflection { bindings })
+}
+
+/// Byte range of a field of a `#[repr(C)]` struct mirrored by a GLSL member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    /// Name of the GLSL member.
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+    pub scalar: Scalar,
+}
+
+/// Rust types of the fields mirrored by GLSL members, with the type of their components.
+pub trait GlslScalar {
+    const SCALAR: Scalar;
+}
+
+macro_rules! glsl_scalar {
+    ($scalar:ident: $($ty:ty),+) => {
+        $(impl GlslScalar for $ty {
+            const SCALAR: Scalar = Scalar::$scalar;
+        })+
+    };
+}
+
+glsl_scalar!(Float: f32, glam::Vec2, glam::Vec3, glam::Vec4, glam::Mat4);
+glsl_scalar!(Int: i32, glam::IVec2, glam::IVec3, glam::IVec4);
+glsl_scalar!(Uint: u32, glam::UVec2, glam::UVec3, glam::UVec4);
+
+/// `Field` of the Rust struct `$ty` mirrored by the GLSL member `$name`, nested fields
+/// such as `camera.eye` are accepted.
+macro_rules! field {
+    ($ty:ty, $($field:ident).+, $name:literal) => {
+        $crate::reflection::Field {
+            name: $name,
+            offset: std::mem::offset_of!($ty, $($field).+) as u32,
+            size: $crate::reflection::field_size(|s: &$ty| &s.$($field).+) as u32,
+            scalar: $crate::reflection::field_scalar(|s: &$ty| &s.$($field).+),
+        }
+    };
+}
+pub(crate) use field;
+
+#[doc(hidden)]
+pub fn field_size<S, F>(_: impl Fn(&S) -> &F) -> usize {
+    std::mem::size_of::<F>()
+}
+
+#[doc(hidden)]
+pub fn field_scalar<S, F: GlslScalar>(_: impl Fn(&S) -> &F) -> Scalar {
+    F::SCALAR
+}
+
+/// Differences between the members of the GLSL struct `glsl` and the fields of the Rust
+/// struct `rust` of `size` bytes.
+pub fn check_struct(glsl: &Struct, rust: &str, fields: &[Field], size: usize) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for (i, member) in glsl.members.iter().enumerate() {
+        let field = match fields.get(i) {
+            Some(field) => field,
+            None => {
+                errors.push(format!(
+                    "`{}.{}` has no counterpart in `{}`",
+                    glsl.name, member.name, rust
+                ));
+                continue;
+            }
+        };
+        if field.name != member.name {
+            errors.push(format!(
+                "member {} of `{}` is `{}` but `{}` has `{}`",
+                i, glsl.name, member.name, rust, field.name
+            ));
+            continue;
+        }
+
+        let offset = member.offset.unwrap_or(0);
+        if offset != field.offset {
+            errors.push(format!(
+                "`{}.{}` is at offset {} in the shader but at {} in `{}`",
+                glsl.name, member.name, offset, field.offset, rust
+            ));
+        }
+        match member.ty.size() {
+            Some(member_size) if member_size != field.size => errors.push(format!(
+                "`{}.{}` is {} bytes in the shader but {} in `{}`",
+                glsl.name, member.name, member_size, field.size, rust
+            )),
+            _ => {}
+        }
+        match member.ty.scalar() {
+            Some(scalar) if scalar != field.scalar => errors.push(format!(
+                "`{}.{}` has {:?} components in the shader but {:?} in `{}`",
+                glsl.name, member.name, scalar, field.scalar, rust
+            )),
+            _ => {}
+        }
+    }
+    for field in fields.iter().skip(glsl.members.len()) {
+        errors.push(format!(
+            "`{}` is not declared in `{}`",
+            field.name, glsl.name
+        ));
+    }
+
+    let end = glsl
+        .members
+        .iter()
+        .map(|m| m.offset.unwrap_or(0) + m.ty.size().unwrap_or(0))
+        .max()
+        .unwrap_or(0);
+    if end as usize > size {
+        errors.push(format!(
+            "`{}` needs {} bytes but `{}` only has {}",
+            glsl.name, end, rust, size
+        ));
+    }
+
+    errors
+}
+
+/// Differences between the runtime array `member` of the block `glsl`, and an array of
+/// the Rust struct `rust` starting at `offset`.
+pub fn check_array(
+    glsl: &Struct,
+    member: usize,
+    offset: usize,
+    rust: &str,
+    fields: &[Field],
+    size: usize,
+) -> Vec<String> {
+    let array = match glsl.members.get(member) {
+        Some(array) => array,
+        None => return vec![format!("`{}` has no member {}", glsl.name, member)],
+    };
+    let (element, stride) = match &array.ty {
+        Type::Array {
+            element, stride, ..
+        } => match element.as_ref() {
+            Type::Struct(element) => (element, stride.unwrap_or(0)),
+            _ => {
+                return vec![format!(
+                    "`{}.{}` is not an array of structs",
+                    glsl.name, array.name
+                )]
+            }
+        },
+        _ => return vec![format!("`{}.{}` is not an array", glsl.name, array.name)],
+    };
+
+    let mut errors = Vec::new();
+    if array.offset.unwrap_or(0) as usize != offset {
+        errors.push(format!(
+            "`{}.{}` is at offset {} in the shader but the buffer places it at {}",
+            glsl.name,
+            array.name,
+            array.offset.unwrap_or(0),
+            offset
+        ));
+    }
+    if stride as usize != size {
+        errors.push(format!(
+            "the elements of `{}.{}` are {} bytes apart in the shader but `{}` is {} bytes",
+            glsl.name, array.name, stride, rust, size
+        ));
+    }
+    errors.extend(check_struct(element, rust, fields, size));
+
+    errors
+}
+
+/// Texture format of a SPIR-V `ImageFormat`.
+fn texture_format(format: u32) -> Option<wgpu::TextureFormat> {
+    let format = match format {
+        1 => wgpu::TextureFormat::Rgba32Float,
+        2 => wgpu::TextureFormat::Rgba16Float,
+        3 => wgpu::TextureFormat::R32Float,
+        4 => wgpu::TextureFormat::Rgba8Unorm,
+        5 => wgpu::TextureFormat::Rgba8Snorm,
+        6 => wgpu::TextureFormat::Rg32Float,
+        7 => wgpu::TextureFormat::Rg16Float,
+        21 => wgpu::TextureFormat::Rgba32Sint,
+        22 => wgpu::TextureFormat::Rgba16Sint,
+        23 => wgpu::TextureFormat::Rgba8Sint,
+        24 => wgpu::TextureFormat::R32Sint,
+        30 => wgpu::TextureFormat::Rgba32Uint,
+        31 => wgpu::TextureFormat::Rgba16Uint,
+        32 => wgpu::TextureFormat::Rgba8Uint,
+        33 => wgpu::TextureFormat::R32Uint,
+        _ => return None,
+    };
+
+    Some(format)
+}
+
+/// View dimension of a SPIR-V `Dim`.
+fn view_dimension(dim: u32) -> Option<wgpu::TextureViewDimension> {
+    match dim {
+        0 => Some(wgpu::TextureViewDimension::D1),
+        1 => Some(wgpu::TextureViewDimension::D2),
+        2 => Some(wgpu::TextureViewDimension::D3),
+        3 => Some(wgpu::TextureViewDimension::Cube),
+        _ => None,
+    }
+}
+
+fn storage_access(read_only: bool, write_only: bool) -> wgpu::StorageTextureAccess {
+    match (read_only, write_only) {
+        (true, _) => wgpu::StorageTextureAccess::ReadOnly,
+        (_, true) => wgpu::StorageTextureAccess::WriteOnly,
+        _ => wgpu::StorageTextureAccess::ReadWrite,
+    }
+}
+
+fn describe_resource(resource: &Resource) -> String {
+    match resource {
+        Resource::UniformBuffer(_) => "a uniform buffer".to_string(),
+        Resource::StorageBuffer { read_only, .. } => {
+            describe_binding_type(&wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage {
+                    read_only: *read_only,
+                },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+        }
+        Resource::SampledImage(image) => match view_dimension(image.dim) {
+            Some(dim) => format!("a sampled {:?} texture", dim),
+            None => "a sampled texture".to_string(),
+        },
+        Resource::StorageImage {
+            image,
+            write_only,
+            read_only,
+        } => match (texture_format(image.format), view_dimension(image.dim)) {
+            (Some(format), Some(dim)) => format!(
+                "a {:?} {:?} {:?} storage texture",
+                storage_access(*read_only, *write_only),
+                format,
+                dim
+            ),
+            _ => "a storage texture of an unsupported format".to_string(),
+        },
+        Resource::Sampler => "a sampler".to_string(),
+        Resource::Other => "an unsupported resource".to_string(),
+    }
+}
+
+fn describe_binding_type(ty: &wgpu::BindingType) -> String {
+    match ty {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            ..
+        } => "a uniform buffer".to_string(),
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            ..
+        } => "a read-only storage buffer".to_string(),
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            ..
+        } => "a storage buffer".to_string(),
+        wgpu::BindingType::Texture { view_dimension, .. } => {
+            format!("a sampled {:?} texture", view_dimension)
+        }
+        wgpu::BindingType::StorageTexture {
+            access,
+            format,
+            view_dimension,
+        } => format!(
+            "a {:?} {:?} {:?} storage texture",
+            access, format, view_dimension
+        ),
+        wgpu::BindingType::Sampler(_) => "a sampler".to_string(),
+    }
+}
+
+fn matches(resource: &Resource, ty: &wgpu::BindingType) -> bool {
+    match (resource, ty) {
+        (
+            Resource::UniformBuffer(_),
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                ..
+            },
+        ) => true,
+        (
+            Resource::StorageBuffer { read_only, .. },
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: layout },
+                ..
+            },
+        ) => read_only == layout,
+        (Resource::SampledImage(image), wgpu::BindingType::Texture { view_dimension, .. }) => {
+            self::view_dimension(image.dim) == Some(*view_dimension)
+        }
+        (
+            Resource::StorageImage {
+                image,
+                write_only,
+                read_only,
+            },
+            wgpu::BindingType::StorageTexture {
+                access,
+                format,
+                view_dimension,
+            },
+        ) => {
+            texture_format(image.format) == Some(*format)
+                && self::view_dimension(image.dim) == Some(*view_dimension)
+                && storage_access(*read_only, *write_only) == *access
+        }
+        (Resource::Sampler, wgpu::BindingType::Sampler(_)) => true,
+        _ => false,
+    }
+}
+
+/// Differences between the resources declared by a shader and the bind group layout of
+/// set 0 made of `entries`. Entries the shader does not use are accepted.
+pub fn check_bindings(
+    reflection: &ShaderReflection,
+    entries: &[wgpu::BindGroupLayoutEntry],
+) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    for binding in &reflection.bindings {
+        let name = match &binding.resource {
+            Resource::UniformBuffer(block) | Resource::StorageBuffer { block, .. }
+                if binding.name.is_empty() =>
+            {
+                &block.name
+            }
+            _ => &binding.name,
+        };
+        let label = format!("binding {} (`{}`)", binding.binding, name);
+
+        if binding.set != 0 {
+            errors.push(format!(
+                "{} is in set {}, only set 0 is bound",
+                label, binding.set
+            ));
+            continue;
+        }
+        match entries.iter().find(|e| e.binding == binding.binding) {
+            Some(entry) if matches(&binding.resource, &entry.ty) => {}
+            Some(entry) => errors.push(format!(
+                "{}: the shader declares {} but the bind group layout has {}",
+                label,
+                describe_resource(&binding.resource),
+                describe_binding_type(&entry.ty)
+            )),
+            None => errors.push(format!(
+                "{}: {} missing from the bind group layout",
+                label,
+                describe_resource(&binding.resource)
+            )),
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    struct Uniforms {
+        time: f32,
+        count: u32,
+    }
+
+    fn block(count: Scalar) -> Struct {
+        Struct {
+            name: "Uniforms".to_string(),
+            members: vec![
+                Member {
+                    name: "u_time".to_string(),
+                    offset: Some(0),
+                    ty: Type::Scalar(Scalar::Float),
+                },
+                Member {
+                    name: "u_count".to_string(),
+                    offset: Some(4),
+                    ty: Type::Scalar(count),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn struct_types() {
+        let fields = [
+            field!(Uniforms, time, "u_time"),
+            field!(Uniforms, count, "u_count"),
+        ];
+        let size = std::mem::size_of::<Uniforms>();
+
+        assert!(check_struct(&block(Scalar::Uint), "Uniforms", &fields, size).is_empty());
+        // same size, different type
+        let errors = check_struct(&block(Scalar::Int), "Uniforms", &fields, size);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("u_count"), "{}", errors[0]);
+    }
+
+    fn member(name: &str, offset: u32, ty: Type) -> Member {
+        Member {
+            name: name.to_string(),
+            offset: Some(offset),
+            ty,
+        }
+    }
+
+    #[repr(C)]
+    struct Pair {
+        a: glam::Vec3,
+        b: glam::Vec3,
+    }
+
+    #[repr(C)]
+    struct PaddedPair {
+        a: glam::Vec3,
+        _pad: f32,
+        b: glam::Vec3,
+    }
+
+    #[test]
+    fn vec3_padding() {
+        // std140 and std430 align a vec3 on 16 bytes, a glam `Vec3` on 4
+        let block = Struct {
+            name: "Pair".to_string(),
+            members: vec![
+                member("a", 0, Type::Vector(Scalar::Float, 3)),
+                member("b", 16, Type::Vector(Scalar::Float, 3)),
+            ],
+        };
+
+        let fields = [field!(Pair, a, "a"), field!(Pair, b, "b")];
+        let errors = check_struct(&block, "Pair", &fields, std::mem::size_of::<Pair>());
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0].contains("offset 16 in the shader but at 12"),
+            "{}",
+            errors[0]
+        );
+        assert!(errors[1].contains("needs 28 bytes"), "{}", errors[1]);
+
+        let fields = [field!(PaddedPair, a, "a"), field!(PaddedPair, b, "b")];
+        let size = std::mem::size_of::<PaddedPair>();
+        assert!(check_struct(&block, "PaddedPair", &fields, size).is_empty());
+    }
+
+    /// `Lights` block of `raymarch.glsl` with the std430 layout: a count then a runtime
+    /// array of `Light`.
+    fn lights_block(array_offset: u32, stride: u32) -> Struct {
+        let float = Type::Scalar(Scalar::Float);
+        let vec3 = Type::Vector(Scalar::Float, 3);
+        let light = Struct {
+            name: "Light".to_string(),
+            members: vec![
+                member("position", 0, vec3.clone()),
+                member("kind", 12, Type::Scalar(Scalar::Int)),
+                member("direction", 16, vec3.clone()),
+                member("intensity", 28, float.clone()),
+                member("color", 32, vec3),
+                member("shadow_k", 44, float.clone()),
+                member("spot_angle", 48, float.clone()),
+                member("spot_softness", 52, float),
+            ],
+        };
+
+        Struct {
+            name: "Lights".to_string(),
+            members: vec![
+                member("light_count", 0, Type::Scalar(Scalar::Int)),
+                member(
+                    "lights",
+                    array_offset,
+                    Type::Array {
+                        element: Box::new(Type::Struct(light)),
+                        length: None,
+                        stride: Some(stride),
+                    },
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn runtime_array() {
+        use crate::utils::Light;
+        let check = |block: &Struct| {
+            check_array(
+                block,
+                1,
+                Light::HEADER_SIZE,
+                "Light",
+                &Light::fields(),
+                std::mem::size_of::<Light>(),
+            )
+        };
+
+        assert!(check(&lights_block(16, 64)).is_empty());
+
+        let errors = check(&lights_block(4, 48));
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0].contains("`Lights.lights` is at offset 4"),
+            "{}",
+            errors[0]
+        );
+        assert!(errors[1].contains("48 bytes apart"), "{}", errors[1]);
+
+        let count = lights_block(16, 64);
+        assert_eq!(
+            check_array(&count, 0, 0, "Light", &Light::fields(), 64),
+            ["`Lights.light_count` is not an array"]
+        );
+    }
+}
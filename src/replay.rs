@@ -0,0 +1,322 @@
+//! Recording of the viewer input, replayed to reproduce a session frame by frame, in the
+//! viewer or offscreen by the replay command.
+//!
+//! A session is saved in RON, like scenes.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use wgpu_sandbox::prelude::winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{
+        DeviceId, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta,
+        TouchPhase, WindowEvent,
+    },
+};
+
+use crate::{camera::CameraController, scene::Scene};
+
+/// Window events used by the viewer, in a form which can be saved.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Resized(PhysicalSize<u32>),
+    Focused(bool),
+    KeyboardInput(KeyboardInput),
+    ModifiersChanged(ModifiersState),
+    CursorMoved(PhysicalPosition<f64>),
+    CursorLeft,
+    MouseWheel(MouseScrollDelta),
+    MouseInput {
+        state: ElementState,
+        button: MouseButton,
+    },
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        let event = match event {
+            WindowEvent::Resized(size) => Self::Resized(*size),
+            WindowEvent::Focused(focused) => Self::Focused(*focused),
+            WindowEvent::KeyboardInput { input, .. } => Self::KeyboardInput(*input),
+            WindowEvent::ModifiersChanged(modifiers) => Self::ModifiersChanged(*modifiers),
+            WindowEvent::CursorMoved { position, .. } => Self::CursorMoved(*position),
+            WindowEvent::CursorLeft { .. } => Self::CursorLeft,
+            WindowEvent::MouseWheel { delta, .. } => Self::MouseWheel(*delta),
+            WindowEvent::MouseInput { state, button, .. } => Self::MouseInput {
+                state: *state,
+                button: *button,
+            },
+            _ => return None,
+        };
+
+        Some(event)
+    }
+
+    #[allow(deprecated)]
+    pub fn to_window_event(self) -> WindowEvent<'static> {
+        // the event never goes back to winit, the id is only compared by the handlers
+        let device_id = unsafe { DeviceId::dummy() };
+        let modifiers = ModifiersState::empty();
+
+        match self {
+            Self::Resized(size) => WindowEvent::Resized(size),
+            Self::Focused(focused) => WindowEvent::Focused(focused),
+            Self::KeyboardInput(input) => WindowEvent::KeyboardInput {
+                device_id,
+                input,
+                is_synthetic: false,
+            },
+            Self::ModifiersChanged(modifiers) => WindowEvent::ModifiersChanged(modifiers),
+            Self::CursorMoved(position) => WindowEvent::CursorMoved {
+                device_id,
+                position,
+                modifiers,
+            },
+            Self::CursorLeft => WindowEvent::CursorLeft { device_id },
+            Self::MouseWheel(delta) => WindowEvent::MouseWheel {
+                device_id,
+                delta,
+                phase: TouchPhase::Moved,
+                modifiers,
+            },
+            Self::MouseInput { state, button } => WindowEvent::MouseInput {
+                device_id,
+                state,
+                button,
+                modifiers,
+            },
+        }
+    }
+}
+
+/// Input of one call to `update`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub dt: Duration,
+    /// Value of `u_time` after the update.
+    pub time: f32,
+    /// The ui had the focus, the events did not reach the camera.
+    pub ui_focused: bool,
+    pub events: Vec<InputEvent>,
+}
+
+impl RecordedFrame {
+    /// Feed the events and the frame time to `controller`, the same way the viewer does.
+    pub fn apply(&self, controller: &mut CameraController, scene: &Scene) {
+        for event in &self.events {
+            match event {
+                // minimized windows have a null size
+                InputEvent::Resized(size) if size.width > 0 && size.height > 0 => {
+                    controller.set_screen_size((size.width as f32, size.height as f32))
+                }
+                _ => {}
+            }
+            if !self.ui_focused {
+                controller.handle_events(&event.to_window_event());
+            }
+        }
+        controller.update(self.dt);
+        controller.scene_actions(scene);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub scene: Option<PathBuf>,
+    /// Resolution of the ray marched image when the recording started.
+    pub resolution: (u32, u32),
+    /// State of the camera when the recording started.
+    pub controller: CameraController,
+    pub frames: Vec<RecordedFrame>,
+}
+
+impl Session {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        ron::from_str(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let ron = ron::to_string(self).map_err(io::Error::other)?;
+        fs::write(path, ron)
+    }
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    session: Session,
+    pending: Vec<InputEvent>,
+}
+
+impl Recorder {
+    pub fn new(
+        scene: Option<PathBuf>,
+        resolution: (u32, u32),
+        controller: CameraController,
+    ) -> Self {
+        Self {
+            session: Session {
+                scene,
+                resolution,
+                controller,
+                frames: Vec::new(),
+            },
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn record_event(&mut self, event: &WindowEvent) {
+        self.pending.extend(InputEvent::from_window_event(event));
+    }
+
+    /// Store the events received since the previous frame along with the frame time.
+    pub fn end_frame(&mut self, dt: Duration, time: f32, ui_focused: bool) {
+        self.session.frames.push(RecordedFrame {
+            dt,
+            time,
+            ui_focused,
+            events: std::mem::take(&mut self.pending),
+        });
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.session.frames.len()
+    }
+
+    pub fn finish(self) -> Session {
+        self.session
+    }
+}
+
+/// Hands out the frames of a session one at a time.
+#[derive(Debug)]
+pub struct Player {
+    session: Session,
+    position: usize,
+}
+
+impl Player {
+    pub fn new(session: Session) -> Self {
+        Self {
+            session,
+            position: 0,
+        }
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Number of frames already played.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn next_frame(&mut self) -> Option<&RecordedFrame> {
+        let frame = self.session.frames.get(self.position)?;
+        self.position += 1;
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu_sandbox::prelude::winit::event::VirtualKeyCode;
+
+    /// A drag with the left button, a scroll, framing the scene, then a drag ignored
+    /// since the ui has the focus, split over a few frames.
+    #[allow(deprecated)]
+    fn frames() -> Vec<(bool, Vec<InputEvent>)> {
+        let cursor = |x, y| InputEvent::CursorMoved(PhysicalPosition::new(x, y));
+        let button = |state| InputEvent::MouseInput {
+            state,
+            button: MouseButton::Left,
+        };
+
+        vec![
+            (false, vec![cursor(100.0, 100.0)]),
+            (
+                false,
+                vec![button(ElementState::Pressed), cursor(120.0, 90.0)],
+            ),
+            (false, vec![cursor(150.0, 95.0)]),
+            (
+                false,
+                vec![InputEvent::Resized(PhysicalSize::new(640, 480))],
+            ),
+            (
+                false,
+                vec![cursor(130.0, 60.0), button(ElementState::Released)],
+            ),
+            (
+                false,
+                vec![InputEvent::MouseWheel(MouseScrollDelta::LineDelta(
+                    0.0, 1.0,
+                ))],
+            ),
+            (
+                false,
+                vec![InputEvent::KeyboardInput(KeyboardInput {
+                    scancode: 0,
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F),
+                    modifiers: ModifiersState::empty(),
+                })],
+            ),
+            (false, vec![]),
+            (
+                true,
+                vec![button(ElementState::Pressed), cursor(10.0, 10.0)],
+            ),
+            (false, vec![]),
+        ]
+    }
+
+    #[test]
+    fn replay_matches_the_recording() {
+        let dt = Duration::from_millis(16);
+        let scene = Scene::builtin();
+        let mut live = CameraController::new(scene.camera, (800.0, 600.0));
+        let mut recorder = Recorder::new(None, (800, 600), live);
+
+        // the viewer feeds the camera and the recorder with the same events
+        let mut cameras = Vec::new();
+        for (i, (ui_focused, events)) in frames().into_iter().enumerate() {
+            for event in events {
+                let event = event.to_window_event();
+                recorder.record_event(&event);
+                if let WindowEvent::Resized(size) = event {
+                    live.set_screen_size((size.width as f32, size.height as f32));
+                }
+                if !ui_focused {
+                    live.handle_events(&event);
+                }
+            }
+            live.update(dt);
+            live.scene_actions(&scene);
+            recorder.end_frame(dt, i as f32, ui_focused);
+            cameras.push(live.camera);
+        }
+        assert_ne!(cameras[0], cameras[cameras.len() - 1]);
+
+        let path =
+            std::env::temp_dir().join(format!("ray_march_session_{}.ron", std::process::id()));
+        recorder.finish().save(&path).unwrap();
+        let session = Session::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let mut controller = session.controller;
+        let mut player = Player::new(session);
+        for (i, camera) in cameras.into_iter().enumerate() {
+            let frame = player.next_frame().unwrap();
+            assert_eq!(frame.time, i as f32);
+            frame.apply(&mut controller, &scene);
+            assert_eq!(controller.camera, camera, "frame {}", i);
+        }
+        assert!(player.next_frame().is_none());
+    }
+}
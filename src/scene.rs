@@ -0,0 +1,950 @@
+//! Scene description files, turned into the `scene.glsl` include of `main.glsl`.
+//!
+//! A scene is written in RON, see `assets/scenes/` for examples.
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use glam::{vec3, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::sdf::{self, Hit};
+use crate::utils::{Light, Material};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneMaterial {
+    pub name: String,
+    pub diffuse: Vec3,
+    pub ambient: Vec3,
+    #[serde(default)]
+    pub specular: Vec3,
+    #[serde(default = "default_specular_exponent")]
+    pub specular_exponent: f32,
+    /// Light emitted by the surface, only used by the path tracer.
+    #[serde(default)]
+    pub emission: Vec3,
+}
+
+fn default_specular_exponent() -> f32 {
+    1.0
+}
+
+impl SceneMaterial {
+    pub fn new(
+        name: &str,
+        diffuse: Vec3,
+        ambient: Vec3,
+        specular: Vec3,
+        specular_exponent: f32,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse,
+            ambient,
+            specular,
+            specular_exponent,
+            emission: Vec3::ZERO,
+        }
+    }
+
+    fn unlit(name: &str, color: Vec3) -> Self {
+        Self {
+            emission: color,
+            ..Self::new(name, color, color, Vec3::ZERO, 1.0)
+        }
+    }
+}
+
+/// Materials of the axes drawn by `background_map`, they always use the ids 0 to 2.
+fn background_materials() -> [SceneMaterial; 3] {
+    [
+        SceneMaterial::unlit("x_axis", vec3(1.0, 0.0, 0.0)),
+        SceneMaterial::unlit("y_axis", vec3(0.0, 1.0, 0.0)),
+        SceneMaterial::unlit("z_axis", vec3(0.0, 0.0, 1.0)),
+    ]
+}
+
+fn material_table(materials: &[SceneMaterial]) -> Vec<SceneMaterial> {
+    background_materials()
+        .into_iter()
+        .chain(materials.iter().cloned())
+        .collect()
+}
+
+/// Material table of the built-in scene of `scene.glsl`.
+pub fn default_material_table() -> Vec<SceneMaterial> {
+    material_table(&default_materials())
+}
+
+fn default_materials() -> Vec<SceneMaterial> {
+    vec![
+        SceneMaterial::new(
+            "red",
+            vec3(0.8, 0.1, 0.08),
+            vec3(0.45, 0.02, 0.05),
+            vec3(0.05, 0.05, 0.05),
+            12.0,
+        ),
+        SceneMaterial::new(
+            "ground",
+            vec3(0.8, 0.7, 0.5),
+            vec3(0.2, 0.3, 0.4),
+            Vec3::ZERO,
+            1.0,
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum LightKind {
+    #[default]
+    Point,
+    Directional,
+    Spot,
+}
+
+/// Light of a scene, `direction` is only used by directional and spot lights.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SceneLight {
+    #[serde(default)]
+    pub kind: LightKind,
+    #[serde(default)]
+    pub position: Vec3,
+    #[serde(default = "default_light_direction")]
+    pub direction: Vec3,
+    #[serde(default = "default_light_color")]
+    pub color: Vec3,
+    #[serde(default = "default_light_intensity")]
+    pub intensity: f32,
+    /// Softness of the shadows, lower values give softer penumbras.
+    #[serde(default = "default_shadow_k")]
+    pub shadow_k: f32,
+    /// Half angle of the cone of a spot light, in radians.
+    #[serde(default = "default_spot_angle")]
+    pub spot_angle: f32,
+    /// Fraction of the cone fading out at its edge.
+    #[serde(default = "default_spot_softness")]
+    pub spot_softness: f32,
+}
+
+fn default_light_direction() -> Vec3 {
+    -Vec3::Y
+}
+
+fn default_light_color() -> Vec3 {
+    Vec3::ONE
+}
+
+fn default_light_intensity() -> f32 {
+    1.0
+}
+
+fn default_shadow_k() -> f32 {
+    32.0
+}
+
+fn default_spot_angle() -> f32 {
+    0.5
+}
+
+fn default_spot_softness() -> f32 {
+    0.2
+}
+
+impl SceneLight {
+    pub fn point(position: Vec3) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: default_light_direction(),
+            color: default_light_color(),
+            intensity: default_light_intensity(),
+            shadow_k: default_shadow_k(),
+            spot_angle: default_spot_angle(),
+            spot_softness: default_spot_softness(),
+        }
+    }
+}
+
+/// Lights of the built-in scene of `scene.glsl`.
+pub fn default_lights() -> Vec<SceneLight> {
+    vec![SceneLight::point(vec3(0.0, 5.0, 5.0))]
+}
+
+/// Primitives of `sdf.glsl`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Shape {
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    Box {
+        size: Vec3,
+    },
+    Capsule {
+        a: Vec3,
+        b: Vec3,
+        radius: f32,
+    },
+    InfiniteCylinder {
+        position: Vec3,
+        direction: Vec3,
+        radius: f32,
+    },
+    Plane {
+        normal: Vec3,
+        offset: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Node of the CSG tree of a scene.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SdfNode {
+    Shape {
+        shape: Shape,
+        material: String,
+    },
+    Union(Vec<SdfNode>),
+    Intersect(Vec<SdfNode>),
+    Substract(Box<SdfNode>, Box<SdfNode>),
+    Translate {
+        offset: Vec3,
+        node: Box<SdfNode>,
+    },
+    Rotate {
+        axis: Axis,
+        angle: f32,
+        node: Box<SdfNode>,
+    },
+}
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Radius of the bounding sphere.
+    pub fn radius(&self) -> f32 {
+        (self.max - self.min).length() * 0.5
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        let min = self.min.max(other.min);
+        // disjoint boxes give an empty box at the corner of the first one
+        Self::new(min, self.max.min(other.max).max(min))
+    }
+
+    /// Bounds of the corners moved by `f`.
+    fn transform(self, f: impl Fn(Vec3) -> Vec3) -> Self {
+        (0..8)
+            .map(|i| {
+                f(vec3(
+                    if i & 1 == 0 { self.min.x } else { self.max.x },
+                    if i & 2 == 0 { self.min.y } else { self.max.y },
+                    if i & 4 == 0 { self.min.z } else { self.max.z },
+                ))
+            })
+            .map(|p| Self::new(p, p))
+            .reduce(Self::union)
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    pub camera: Camera,
+    pub materials: Vec<SceneMaterial>,
+    pub lights: Vec<SceneLight>,
+    pub root: SdfNode,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse(ron::Error),
+    UnknownMaterial(String),
+    EmptyOperator,
+    /// NaN or infinite value, the name says where.
+    NonFinite(String),
+    TooManyMaterials,
+    TooManyLights,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "cannot read scene: {}", e),
+            Self::Parse(e) => write!(f, "invalid scene: {}", e),
+            Self::UnknownMaterial(name) => write!(f, "unknown material `{}`", name),
+            Self::EmptyOperator => write!(f, "union and intersection need at least one node"),
+            Self::NonFinite(name) => write!(f, "non-finite value in {}", name),
+            Self::TooManyMaterials => write!(
+                f,
+                "scene has more than {} materials",
+                Material::MAX_COUNT - background_materials().len()
+            ),
+            Self::TooManyLights => write!(f, "scene has more than {} lights", Light::MAX_COUNT),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<ron::Error> for SceneError {
+    fn from(e: ron::Error) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl Scene {
+    /// CPU counterpart of the built-in scene of `scene.glsl`.
+    pub fn builtin() -> Self {
+        Self {
+            camera: Camera::initial(),
+            materials: default_materials(),
+            lights: default_lights(),
+            root: SdfNode::sphere(vec3(0.0, 1.1, 0.0), 1.0)
+                .material("red")
+                .union(SdfNode::boxed(vec3(10.0, 0.1, 10.0)).material("ground")),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let scene: Self = ron::from_str(&fs::read_to_string(path)?)?;
+        // catch errors at load time rather than when compiling the shader
+        scene.check_finite()?;
+        scene.to_glsl()?;
+
+        Ok(scene)
+    }
+
+    /// Reject NaN and infinite values, which ron accepts but which are not valid GLSL
+    /// literals and break the uploaded camera, materials and lights.
+    fn check_finite(&self) -> Result<(), SceneError> {
+        let non_finite = |name: String| Err(SceneError::NonFinite(name));
+
+        let camera = &self.camera;
+        if !(camera.eye.is_finite() && camera.target.is_finite() && camera.fov.is_finite()) {
+            return non_finite("the camera".to_string());
+        }
+        for m in &self.materials {
+            let colors = [m.diffuse, m.ambient, m.specular];
+            if !(colors.iter().all(|c| c.is_finite()) && m.specular_exponent.is_finite()) {
+                return non_finite(format!("material `{}`", m.name));
+            }
+        }
+        for (i, l) in self.lights.iter().enumerate() {
+            let values = [l.intensity, l.shadow_k, l.spot_angle, l.spot_softness];
+            if !(l.position.is_finite()
+                && l.direction.is_finite()
+                && l.color.is_finite()
+                && values.iter().all(|v| v.is_finite()))
+            {
+                return non_finite(format!("light {}", i));
+            }
+        }
+        if !self.root.is_finite() {
+            return non_finite("the sdf tree".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Generate the `scene.glsl` include with the `scene()` function. Materials and
+    /// lights are uploaded separately, see [`Scene::material_table`].
+    pub fn to_glsl(&self) -> Result<String, SceneError> {
+        if self.material_table().len() > Material::MAX_COUNT {
+            return Err(SceneError::TooManyMaterials);
+        }
+        if self.lights.len() > Light::MAX_COUNT {
+            return Err(SceneError::TooManyLights);
+        }
+
+        Ok(format!(
+            "// generated from a scene file\n\n{}",
+            self.root.scene_function(&|name| self.material_id(name))?
+        ))
+    }
+
+    /// Evaluate `scene()` on the CPU, unknown materials get the id -1.
+    pub fn eval(&self, p: Vec3) -> Hit {
+        self.root
+            .eval(p, &|name| self.material_id(name).unwrap_or(-1))
+    }
+
+    /// Distance along the ray to the scene surface, `None` when it is missed. The axes
+    /// are not part of the scene.
+    pub fn ray_cast(&self, origin: Vec3, dir: Vec3) -> Option<f32> {
+        let hit = sdf::ray_cast(|p| self.eval(p), origin, dir);
+        (hit.dist < sdf::MAX_DIST).then_some(hit.dist)
+    }
+
+    /// Bounds of the finite part of the scene, `None` when it is only made of planes and
+    /// infinite cylinders.
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.root.bounds()
+    }
+
+    /// Materials indexed by their id in the shader, the axes come first.
+    pub fn material_table(&self) -> Vec<SceneMaterial> {
+        material_table(&self.materials)
+    }
+
+    /// Material id used in the shader for the material `name`.
+    pub fn material_id(&self, name: &str) -> Option<i32> {
+        self.materials
+            .iter()
+            .position(|m| m.name == name)
+            .map(|i| (i + background_materials().len()) as i32)
+    }
+}
+
+/// Builder API, e.g.
+/// `SdfNode::sphere(Vec3::ZERO, 1.0).union(SdfNode::boxed(Vec3::ONE)).translate(Vec3::Y)`.
+/// Shapes are created without material, see [`SdfNode::material`].
+impl SdfNode {
+    fn shape(shape: Shape) -> Self {
+        Self::Shape {
+            shape,
+            material: String::new(),
+        }
+    }
+
+    pub fn sphere(center: Vec3, radius: f32) -> Self {
+        Self::shape(Shape::Sphere { center, radius })
+    }
+
+    pub fn boxed(size: Vec3) -> Self {
+        Self::shape(Shape::Box { size })
+    }
+
+    pub fn capsule(a: Vec3, b: Vec3, radius: f32) -> Self {
+        Self::shape(Shape::Capsule { a, b, radius })
+    }
+
+    pub fn infinite_cylinder(position: Vec3, direction: Vec3, radius: f32) -> Self {
+        Self::shape(Shape::InfiniteCylinder {
+            position,
+            direction,
+            radius,
+        })
+    }
+
+    pub fn plane(normal: Vec3, offset: f32) -> Self {
+        Self::shape(Shape::Plane { normal, offset })
+    }
+
+    /// Assign the material `name` to every shape of the tree.
+    pub fn material(mut self, name: &str) -> Self {
+        self.set_material(name);
+        self
+    }
+
+    fn set_material(&mut self, name: &str) {
+        match self {
+            Self::Shape { material, .. } => *material = name.to_string(),
+            Self::Union(nodes) | Self::Intersect(nodes) => {
+                nodes.iter_mut().for_each(|n| n.set_material(name))
+            }
+            Self::Substract(a, b) => {
+                a.set_material(name);
+                b.set_material(name);
+            }
+            Self::Translate { node, .. } | Self::Rotate { node, .. } => node.set_material(name),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        match self {
+            Self::Union(mut nodes) => {
+                nodes.push(other);
+                Self::Union(nodes)
+            }
+            node => Self::Union(vec![node, other]),
+        }
+    }
+
+    pub fn intersect(self, other: Self) -> Self {
+        match self {
+            Self::Intersect(mut nodes) => {
+                nodes.push(other);
+                Self::Intersect(nodes)
+            }
+            node => Self::Intersect(vec![node, other]),
+        }
+    }
+
+    /// Carve `other` out of `self`.
+    pub fn substract(self, other: Self) -> Self {
+        Self::Substract(Box::new(self), Box::new(other))
+    }
+
+    pub fn translate(self, offset: Vec3) -> Self {
+        Self::Translate {
+            offset,
+            node: Box::new(self),
+        }
+    }
+
+    pub fn rotate(self, axis: Axis, angle: f32) -> Self {
+        Self::Rotate {
+            axis,
+            angle,
+            node: Box::new(self),
+        }
+    }
+
+    /// Full `Hit scene(vec3 p)` function of the tree.
+    pub fn scene_function(
+        &self,
+        material_id: &dyn Fn(&str) -> Option<i32>,
+    ) -> Result<String, SceneError> {
+        Ok(format!(
+            "Hit scene(vec3 p) {{\n\treturn {};\n}}\n",
+            self.glsl("p", material_id)?
+        ))
+    }
+
+    /// CPU counterpart of the code generated by [`SdfNode::scene_function`].
+    /// Empty unions and intersections are infinitely far away.
+    pub fn eval(&self, p: Vec3, material_id: &dyn Fn(&str) -> i32) -> Hit {
+        match self {
+            Self::Shape { shape, material } => Hit::new(shape.eval(p), material_id(material)),
+            Self::Union(nodes) => fold_eval(sdf::op_union, nodes, p, material_id),
+            Self::Intersect(nodes) => fold_eval(sdf::op_intersect, nodes, p, material_id),
+            Self::Substract(a, b) => {
+                sdf::op_substract(a.eval(p, material_id), b.eval(p, material_id))
+            }
+            Self::Translate { offset, node } => node.eval(sdf::op_tx(p, *offset), material_id),
+            Self::Rotate { axis, angle, node } => {
+                let p = match axis {
+                    Axis::X => sdf::op_rotate_x(p, *angle),
+                    Axis::Y => sdf::op_rotate_y(p, *angle),
+                    Axis::Z => sdf::op_rotate_z(p, *angle),
+                };
+                node.eval(p, material_id)
+            }
+        }
+    }
+
+    /// Whether all the values of the tree are finite.
+    fn is_finite(&self) -> bool {
+        match self {
+            Self::Shape { shape, .. } => shape.is_finite(),
+            Self::Union(nodes) | Self::Intersect(nodes) => nodes.iter().all(Self::is_finite),
+            Self::Substract(a, b) => a.is_finite() && b.is_finite(),
+            Self::Translate { offset, node } => offset.is_finite() && node.is_finite(),
+            Self::Rotate { angle, node, .. } => angle.is_finite() && node.is_finite(),
+        }
+    }
+
+    /// Conservative bounds of the node, `None` when it is unbounded. Unbounded shapes of a
+    /// union are left out, so the bounds cover its finite part.
+    pub fn bounds(&self) -> Option<Bounds> {
+        match self {
+            Self::Shape { shape, .. } => shape.bounds(),
+            Self::Union(nodes) => nodes.iter().filter_map(Self::bounds).reduce(Bounds::union),
+            Self::Intersect(nodes) => nodes
+                .iter()
+                .filter_map(Self::bounds)
+                .reduce(Bounds::intersection),
+            Self::Substract(a, _) => a.bounds(),
+            Self::Translate { offset, node } => node.bounds().map(|b| b.transform(|p| p + *offset)),
+            // the node is evaluated at the point rotated by -angle, so its bounds turn by angle
+            Self::Rotate { axis, angle, node } => node.bounds().map(|b| {
+                b.transform(|p| match axis {
+                    Axis::X => sdf::op_rotate_x(p, -*angle),
+                    Axis::Y => sdf::op_rotate_y(p, -*angle),
+                    Axis::Z => sdf::op_rotate_z(p, -*angle),
+                })
+            }),
+        }
+    }
+
+    /// GLSL expression of type `Hit` evaluating the node at the point expression `p`.
+    fn glsl(
+        &self,
+        p: &str,
+        material_id: &dyn Fn(&str) -> Option<i32>,
+    ) -> Result<String, SceneError> {
+        Ok(match self {
+            Self::Shape { shape, material } => {
+                let id = material_id(material)
+                    .ok_or_else(|| SceneError::UnknownMaterial(material.clone()))?;
+                format!("Hit({}, {})", shape.glsl(p), id)
+            }
+            Self::Union(nodes) => fold_glsl("opUnion", nodes, p, material_id)?,
+            Self::Intersect(nodes) => fold_glsl("opIntersect", nodes, p, material_id)?,
+            Self::Substract(a, b) => format!(
+                "opSubstract({}, {})",
+                a.glsl(p, material_id)?,
+                b.glsl(p, material_id)?
+            ),
+            Self::Translate { offset, node } => {
+                let p = format!("opTx({}, {})", p, glsl_vec3(*offset));
+                node.glsl(&p, material_id)?
+            }
+            Self::Rotate { axis, angle, node } => {
+                let op = match axis {
+                    Axis::X => "opRotateX",
+                    Axis::Y => "opRotateY",
+                    Axis::Z => "opRotateZ",
+                };
+                let p = format!("{}({}, {})", op, p, glsl_float(*angle));
+                node.glsl(&p, material_id)?
+            }
+        })
+    }
+}
+
+fn fold_glsl(
+    op: &str,
+    nodes: &[SdfNode],
+    p: &str,
+    material_id: &dyn Fn(&str) -> Option<i32>,
+) -> Result<String, SceneError> {
+    let (first, rest) = nodes.split_first().ok_or(SceneError::EmptyOperator)?;
+
+    rest.iter()
+        .try_fold(first.glsl(p, material_id)?, |acc, node| {
+            Ok(format!("{}({}, {})", op, acc, node.glsl(p, material_id)?))
+        })
+}
+
+fn fold_eval(
+    op: fn(Hit, Hit) -> Hit,
+    nodes: &[SdfNode],
+    p: Vec3,
+    material_id: &dyn Fn(&str) -> i32,
+) -> Hit {
+    nodes
+        .iter()
+        .map(|node| node.eval(p, material_id))
+        .reduce(op)
+        .unwrap_or(Hit::new(f32::INFINITY, -1))
+}
+
+impl Shape {
+    fn eval(&self, p: Vec3) -> f32 {
+        match self {
+            Self::Sphere { center, radius } => sdf::sd_sphere(p, *center, *radius),
+            Self::Box { size } => sdf::sd_box(p, *size),
+            Self::Capsule { a, b, radius } => sdf::sd_capsule(p, *a, *b, *radius),
+            Self::InfiniteCylinder {
+                position,
+                direction,
+                radius,
+            } => sdf::sd_infinite_cylinder(p, *position, *direction, *radius),
+            Self::Plane { normal, offset } => sdf::sd_plane(p, *normal, *offset),
+        }
+    }
+
+    fn is_finite(&self) -> bool {
+        match self {
+            Self::Sphere { center, radius } => center.is_finite() && radius.is_finite(),
+            Self::Box { size } => size.is_finite(),
+            Self::Capsule { a, b, radius } => a.is_finite() && b.is_finite() && radius.is_finite(),
+            Self::InfiniteCylinder {
+                position,
+                direction,
+                radius,
+            } => position.is_finite() && direction.is_finite() && radius.is_finite(),
+            Self::Plane { normal, offset } => normal.is_finite() && offset.is_finite(),
+        }
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        match self {
+            Self::Sphere { center, radius } => Some(Bounds::new(
+                *center - Vec3::splat(*radius),
+                *center + Vec3::splat(*radius),
+            )),
+            Self::Box { size } => Some(Bounds::new(-*size, *size)),
+            Self::Capsule { a, b, radius } => Some(Bounds::new(
+                a.min(*b) - Vec3::splat(*radius),
+                a.max(*b) + Vec3::splat(*radius),
+            )),
+            Self::InfiniteCylinder { .. } | Self::Plane { .. } => None,
+        }
+    }
+
+    /// GLSL expression of the distance to the shape at the point expression `p`.
+    fn glsl(&self, p: &str) -> String {
+        match self {
+            Self::Sphere { center, radius } => format!(
+                "sdSphere({}, {}, {})",
+                p,
+                glsl_vec3(*center),
+                glsl_float(*radius)
+            ),
+            Self::Box { size } => format!("sdBox({}, {})", p, glsl_vec3(*size)),
+            Self::Capsule { a, b, radius } => format!(
+                "sdCapsule({}, {}, {}, {})",
+                p,
+                glsl_vec3(*a),
+                glsl_vec3(*b),
+                glsl_float(*radius)
+            ),
+            Self::InfiniteCylinder {
+                position,
+                direction,
+                radius,
+            } => format!(
+                "sdInfiniteCylinder({}, {}, {}, {})",
+                p,
+                glsl_vec3(*position),
+                glsl_vec3(*direction),
+                glsl_float(*radius)
+            ),
+            Self::Plane { normal, offset } => format!(
+                "sdPlane({}, {}, {})",
+                p,
+                glsl_vec3(*normal),
+                glsl_float(*offset)
+            ),
+        }
+    }
+}
+
+// debug formatting of floats always keeps a decimal point or an exponent,
+// so the literals stay floats in glsl
+fn glsl_float(v: f32) -> String {
+    format!("{:?}", v)
+}
+
+fn glsl_vec3(v: Vec3) -> String {
+    format!("vec3({:?}, {:?}, {:?})", v.x, v.y, v.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const RED: i32 = 3;
+    const GROUND: i32 = 4;
+
+    fn scene(root: SdfNode) -> Scene {
+        Scene {
+            camera: Camera::default(),
+            materials: vec![
+                SceneMaterial::unlit("red", vec3(0.8, 0.1, 0.08)),
+                SceneMaterial::unlit("ground", vec3(0.8, 0.7, 0.5)),
+            ],
+            lights: default_lights(),
+            root,
+        }
+    }
+
+    fn assert_hit(hit: Hit, dist: f32, id: i32) {
+        assert!((hit.dist - dist).abs() < 1e-5, "{} != {}", hit.dist, dist);
+        assert_eq!(hit.id, id);
+    }
+
+    #[test]
+    fn eval_matches_sdf() {
+        let scene = scene(
+            SdfNode::sphere(vec3(0.0, 1.1, 0.0), 1.0)
+                .material("red")
+                .union(SdfNode::boxed(vec3(10.0, 0.1, 10.0)).material("ground")),
+        );
+        let p = vec3(0.5, 3.0, -0.2);
+        assert_hit(
+            scene.eval(p),
+            sdf::sd_sphere(p, vec3(0.0, 1.1, 0.0), 1.0),
+            RED,
+        );
+        let p = vec3(4.0, -2.0, 1.0);
+        assert_hit(scene.eval(p), sdf::sd_box(p, vec3(10.0, 0.1, 10.0)), GROUND);
+    }
+
+    #[test]
+    fn eval_substract_and_intersect() {
+        let scene = scene(
+            SdfNode::boxed(Vec3::ONE)
+                .material("ground")
+                .substract(SdfNode::sphere(Vec3::ZERO, 0.5).material("red")),
+        );
+        // carved out by the sphere
+        assert_hit(scene.eval(Vec3::ZERO), 0.5, RED);
+        assert_hit(scene.eval(vec3(3.0, 0.0, 0.0)), 2.0, GROUND);
+
+        let scene = self::scene(
+            SdfNode::boxed(Vec3::ONE)
+                .intersect(SdfNode::sphere(Vec3::ZERO, 1.2))
+                .material("red"),
+        );
+        assert_hit(scene.eval(vec3(0.0, 2.0, 0.0)), 1.0, RED);
+    }
+
+    #[test]
+    fn eval_transforms() {
+        let capsule = SdfNode::capsule(Vec3::ZERO, vec3(2.0, 0.0, 0.0), 0.5).material("red");
+
+        let translated = scene(capsule.clone().translate(vec3(0.0, 3.0, 0.0)));
+        assert_hit(translated.eval(vec3(1.0, 3.0, 0.0)), -0.5, RED);
+        assert_hit(translated.eval(vec3(1.0, 0.0, 0.0)), 2.5, RED);
+
+        // the translation is undone before the rotation, so the capsule turns around
+        // its own origin and is then moved
+        let composed = scene(
+            capsule
+                .rotate(Axis::Z, FRAC_PI_2)
+                .translate(vec3(5.0, 0.0, 0.0)),
+        );
+        let p = vec3(6.0, 0.0, 0.0);
+        let expected = sdf::sd_capsule(
+            sdf::op_rotate_z(sdf::op_tx(p, vec3(5.0, 0.0, 0.0)), FRAC_PI_2),
+            Vec3::ZERO,
+            vec3(2.0, 0.0, 0.0),
+            0.5,
+        );
+        assert_hit(composed.eval(p), expected, RED);
+        // the capsule now goes from the translated origin towards -y
+        assert_hit(composed.eval(vec3(5.0, -1.0, 0.0)), -0.5, RED);
+        assert_hit(composed.eval(vec3(5.0, 1.0, 0.0)), 0.5, RED);
+    }
+
+    #[test]
+    fn eval_empty_union() {
+        let scene = scene(SdfNode::Union(Vec::new()));
+        assert_eq!(scene.eval(Vec3::ZERO).dist, f32::INFINITY);
+    }
+
+    #[test]
+    fn glsl_snapshot() {
+        let scene = scene(
+            SdfNode::sphere(vec3(0.0, 1.0, 0.0), 0.5)
+                .material("red")
+                .union(
+                    SdfNode::boxed(vec3(1.0, 2.0, 3.0))
+                        .rotate(Axis::Y, 0.5)
+                        .translate(vec3(1.0, 0.0, -1.0))
+                        .material("ground"),
+                ),
+        );
+
+        assert_eq!(
+            scene
+                .root
+                .scene_function(&|name| scene.material_id(name))
+                .unwrap(),
+            "Hit scene(vec3 p) {\n\
+             \treturn opUnion(Hit(sdSphere(p, vec3(0.0, 1.0, 0.0), 0.5), 3), \
+             Hit(sdBox(opRotateY(opTx(p, vec3(1.0, 0.0, -1.0)), 0.5), vec3(1.0, 2.0, 3.0)), 4));\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn glsl_errors() {
+        let unknown = scene(SdfNode::sphere(Vec3::ZERO, 1.0).material("missing"));
+        assert!(matches!(
+            unknown.to_glsl(),
+            Err(SceneError::UnknownMaterial(name)) if name == "missing"
+        ));
+        let empty = scene(SdfNode::Intersect(Vec::new()));
+        assert!(matches!(empty.to_glsl(), Err(SceneError::EmptyOperator)));
+        let mut bright = scene(SdfNode::sphere(Vec3::ZERO, 1.0).material("red"));
+        bright.lights = vec![SceneLight::point(Vec3::Y); Light::MAX_COUNT + 1];
+        assert!(matches!(bright.to_glsl(), Err(SceneError::TooManyLights)));
+    }
+
+    #[test]
+    fn non_finite_values() {
+        let sphere = SdfNode::sphere(Vec3::ZERO, 1.0).material("red");
+        assert!(scene(sphere.clone()).check_finite().is_ok());
+
+        let nan = scene(SdfNode::sphere(Vec3::ZERO, f32::NAN).material("red"));
+        assert!(matches!(nan.check_finite(), Err(SceneError::NonFinite(_))));
+
+        let mut inf = scene(sphere);
+        inf.lights[0].intensity = f32::INFINITY;
+        assert!(matches!(inf.check_finite(), Err(SceneError::NonFinite(_))));
+    }
+
+    #[test]
+    fn builtin_scene() {
+        let scene = Scene::builtin();
+        assert!(scene.check_finite().is_ok());
+        assert!(scene.to_glsl().is_ok());
+
+        // the red sphere stands on the ground box
+        let bounds = scene.bounds().unwrap();
+        assert_eq!(bounds.min, vec3(-10.0, -0.1, -10.0));
+        assert_eq!(bounds.max, vec3(10.0, 2.1, 10.0));
+    }
+
+    #[test]
+    fn bounds() {
+        let plane = SdfNode::plane(Vec3::Y, 0.0);
+        assert!(scene(plane.clone()).bounds().is_none());
+
+        // unbounded shapes of a union are left out
+        let sphere = SdfNode::sphere(vec3(0.0, 2.0, 0.0), 1.0);
+        let bounds = scene(sphere.clone().union(plane)).bounds().unwrap();
+        assert_eq!(
+            (bounds.min, bounds.max),
+            (vec3(-1.0, 1.0, -1.0), vec3(1.0, 3.0, 1.0))
+        );
+
+        let moved = scene(
+            SdfNode::boxed(vec3(1.0, 2.0, 3.0))
+                .rotate(Axis::Y, FRAC_PI_2)
+                .translate(vec3(5.0, 0.0, 0.0)),
+        );
+        let bounds = moved.bounds().unwrap();
+        assert!(
+            bounds.min.abs_diff_eq(vec3(2.0, -2.0, -1.0), 1e-5),
+            "{}",
+            bounds.min
+        );
+        assert!(
+            bounds.max.abs_diff_eq(vec3(8.0, 2.0, 1.0), 1e-5),
+            "{}",
+            bounds.max
+        );
+    }
+
+    #[test]
+    fn ray_cast() {
+        let scene = Scene::builtin();
+        let top = scene.ray_cast(vec3(0.0, 5.0, 0.0), -Vec3::Y).unwrap();
+        assert!((top - 2.9).abs() < 1e-3, "{}", top);
+        let ground = scene.ray_cast(vec3(5.0, 5.0, 0.0), -Vec3::Y).unwrap();
+        assert!((ground - 4.9).abs() < 1e-3, "{}", ground);
+        assert!(scene.ray_cast(vec3(0.0, 5.0, 0.0), Vec3::Y).is_none());
+    }
+}
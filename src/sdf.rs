@@ -0,0 +1,243 @@
+//! CPU implementation of the distance functions of `assets/shaders/sdf.glsl`.
+//!
+//! Every function mirrors its GLSL counterpart so a scene can be evaluated on the CPU
+//! and checked against the shader.
+
+use glam::{Mat3, Vec3};
+
+// default settings of `raymarch.glsl`, see `ShaderDefines`
+pub const MAX_STEPS: u32 = 256;
+pub const MIN_HIT_DIST: f32 = 0.001;
+pub const MAX_DIST: f32 = 100.0;
+
+/// Same layout as the `Hit` struct of `utils.glsl`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hit {
+    pub dist: f32,
+    pub id: i32,
+}
+
+impl Hit {
+    pub fn new(dist: f32, id: i32) -> Self {
+        Self { dist, id }
+    }
+}
+
+pub fn sd_sphere(p: Vec3, c: Vec3, r: f32) -> f32 {
+    (p - c).length() - r
+}
+
+pub fn sd_plane(p: Vec3, normal: Vec3, o: f32) -> f32 {
+    p.dot(normal) - o
+}
+
+pub fn sd_xz_plane(p: Vec3, yoffset: f32) -> f32 {
+    p.y - yoffset
+}
+
+pub fn sd_xy_plane(p: Vec3, zoffset: f32) -> f32 {
+    p.z - zoffset
+}
+
+pub fn sd_yz_plane(p: Vec3, xoffset: f32) -> f32 {
+    p.x - xoffset
+}
+
+pub fn sd_box(p: Vec3, b: Vec3) -> f32 {
+    let q = p.abs() - b;
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+}
+
+/// Capsule between the points `a` and `b`.
+pub fn sd_capsule(p: Vec3, a: Vec3, b: Vec3, r: f32) -> f32 {
+    let ap = p - a;
+    let ab = b - a;
+    let h = (ap.dot(ab) / ab.dot(ab)).clamp(0.0, 1.0);
+    (ap - h * ab).length() - r
+}
+
+/// Capsule lying on the x axis between `0` and `d`, the `sdCapsule(p, d, r)` overload.
+pub fn sd_capsule_x(mut p: Vec3, d: f32, r: f32) -> f32 {
+    p.x -= p.x.clamp(0.0, d);
+    p.length() - r
+}
+
+pub fn sd_infinite_cylinder(p: Vec3, pos: Vec3, dir: Vec3, r: f32) -> f32 {
+    let q = p - pos;
+    let d = q.cross(dir).length() / dir.length();
+
+    d - r
+}
+
+pub fn op_union(d1: Hit, d2: Hit) -> Hit {
+    if d1.dist < d2.dist {
+        d1
+    } else {
+        d2
+    }
+}
+
+pub fn op_intersect(d1: Hit, d2: Hit) -> Hit {
+    if d1.dist > d2.dist {
+        d1
+    } else {
+        d2
+    }
+}
+
+pub fn op_substract(d1: Hit, d2: Hit) -> Hit {
+    if d1.dist > -d2.dist {
+        d1
+    } else {
+        Hit::new(-d2.dist, d2.id)
+    }
+}
+
+/// Sphere tracing of `map` from `ro` along `rd`, the `ray_cast` function of
+/// `raymarch.glsl`. A distance of at least `MAX_DIST` means the ray missed.
+pub fn ray_cast(map: impl Fn(Vec3) -> Hit, ro: Vec3, rd: Vec3) -> Hit {
+    let mut t = Hit::new(0.001, 0);
+    for _ in 0..MAX_STEPS {
+        let d = map(ro + rd * t.dist);
+        if d.dist <= MIN_HIT_DIST || t.dist >= MAX_DIST {
+            break;
+        }
+        t.dist += d.dist;
+        t.id = d.id;
+    }
+
+    t
+}
+
+pub fn op_tx(p: Vec3, translation: Vec3) -> Vec3 {
+    p - translation
+}
+
+// the matrices below are written column by column, like the glsl mat3 constructor
+pub fn op_rotate_x(p: Vec3, angle: f32) -> Vec3 {
+    let c = (-angle).cos();
+    let s = (-angle).sin();
+    let rot = Mat3::from_cols_array(&[1.0, 0.0, 0.0, 0.0, c, -s, 0.0, s, c]);
+
+    rot * p
+}
+
+pub fn op_rotate_y(p: Vec3, angle: f32) -> Vec3 {
+    let c = (-angle).cos();
+    let s = (-angle).sin();
+    let rot = Mat3::from_cols_array(&[c, 0.0, -s, 0.0, 1.0, 0.0, s, 0.0, c]);
+
+    rot * p
+}
+
+pub fn op_rotate_z(p: Vec3, angle: f32) -> Vec3 {
+    let c = (-angle).cos();
+    let s = (-angle).sin();
+    let rot = Mat3::from_cols_array(&[c, -s, 0.0, s, c, 0.0, 0.0, 0.0, 1.0]);
+
+    rot * p
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const EPS: f32 = 1e-5;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < EPS, "{} != {}", a, b);
+    }
+
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, EPS), "{} != {}", a, b);
+    }
+
+    #[test]
+    fn sphere() {
+        let c = Vec3::new(1.0, 2.0, 3.0);
+        assert_close(sd_sphere(c, c, 2.0), -2.0);
+        assert_close(sd_sphere(c + Vec3::new(0.0, 2.0, 0.0), c, 2.0), 0.0);
+        assert_close(sd_sphere(c + Vec3::new(3.0, 0.0, 4.0), c, 2.0), 3.0);
+    }
+
+    #[test]
+    fn planes() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        assert_close(sd_xz_plane(p, 0.5), 1.5);
+        assert_close(sd_xy_plane(p, 4.0), -1.0);
+        assert_close(sd_yz_plane(p, 1.0), 0.0);
+        assert_close(sd_plane(p, Vec3::Y, 0.5), sd_xz_plane(p, 0.5));
+    }
+
+    #[test]
+    fn box_faces_and_corners() {
+        let b = Vec3::new(1.0, 2.0, 3.0);
+        assert_close(sd_box(Vec3::ZERO, b), -1.0);
+        assert_close(sd_box(Vec3::new(0.0, 0.0, 3.0), b), 0.0);
+        assert_close(sd_box(Vec3::new(0.0, 4.0, 0.0), b), 2.0);
+        assert_close(sd_box(b, b), 0.0);
+        assert_close(sd_box(b + Vec3::new(1.0, 2.0, 2.0), b), 3.0);
+    }
+
+    #[test]
+    fn capsule() {
+        let a = Vec3::ZERO;
+        let b = Vec3::new(0.0, 4.0, 0.0);
+        assert_close(sd_capsule(Vec3::new(1.0, 2.0, 0.0), a, b, 0.5), 0.5);
+        assert_close(sd_capsule(Vec3::new(0.0, 7.0, 0.0), a, b, 0.5), 2.5);
+        assert_close(sd_capsule(Vec3::new(0.0, -1.0, 0.0), a, b, 0.5), 0.5);
+        assert_close(
+            sd_capsule_x(Vec3::new(2.0, 3.0, 0.0), 4.0, 1.0),
+            sd_capsule(Vec3::new(2.0, 3.0, 0.0), a, Vec3::new(4.0, 0.0, 0.0), 1.0),
+        );
+        assert_close(sd_capsule_x(Vec3::new(-3.0, 0.0, 4.0), 4.0, 1.0), 4.0);
+    }
+
+    #[test]
+    fn infinite_cylinder() {
+        let pos = Vec3::new(1.0, 0.0, 0.0);
+        let dir = Vec3::new(0.0, 2.0, 0.0);
+        assert_close(
+            sd_infinite_cylinder(Vec3::new(4.0, 100.0, 4.0), pos, dir, 1.0),
+            4.0,
+        );
+        assert_close(sd_infinite_cylinder(pos, pos, dir, 1.0), -1.0);
+    }
+
+    #[test]
+    fn operators() {
+        let a = Hit::new(1.0, 1);
+        let b = Hit::new(2.0, 2);
+        assert_eq!(op_union(a, b), a);
+        assert_eq!(op_union(b, a), a);
+        assert_eq!(op_intersect(a, b), b);
+        assert_eq!(op_intersect(b, a), b);
+
+        // outside of the subtracted shape the distance is unchanged
+        assert_eq!(op_substract(a, Hit::new(0.5, 2)), a);
+        // inside of it, the distance to its surface with its id
+        assert_eq!(op_substract(a, Hit::new(-3.0, 2)), Hit::new(3.0, 2));
+    }
+
+    #[test]
+    fn transforms() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        assert_close_vec(op_tx(p, Vec3::ONE), Vec3::new(0.0, 1.0, 2.0));
+        assert_close_vec(op_rotate_x(Vec3::Y, FRAC_PI_2), Vec3::Z);
+        assert_close_vec(op_rotate_y(Vec3::Z, FRAC_PI_2), -Vec3::X);
+        assert_close_vec(op_rotate_z(Vec3::X, FRAC_PI_2), Vec3::Y);
+        assert_close_vec(op_rotate_y(p, 0.0), p);
+    }
+
+    #[test]
+    fn ray_cast_hits_sphere() {
+        let map = |p: Vec3| Hit::new(sd_sphere(p, Vec3::new(0.0, 0.0, 5.0), 1.0), 3);
+        let hit = ray_cast(map, Vec3::ZERO, Vec3::Z);
+        assert!((hit.dist - 4.0).abs() < MIN_HIT_DIST * 2.0);
+        assert_eq!(hit.id, 3);
+
+        let miss = ray_cast(map, Vec3::ZERO, -Vec3::Z);
+        assert!(miss.dist >= MAX_DIST);
+    }
+}
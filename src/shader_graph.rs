@@ -0,0 +1,120 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Files included by each entry shader, directly or through another include, as
+/// reported by the shaderc include callback.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeGraph {
+    includes: HashMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl IncludeGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the includes of `entry` by the ones found while compiling it.
+    pub fn set_includes<I: IntoIterator<Item = PathBuf>>(&mut self, entry: &Path, includes: I) {
+        self.includes
+            .insert(entry.to_path_buf(), includes.into_iter().collect());
+    }
+
+    /// Entry shaders and every file they include.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut files: BTreeSet<PathBuf> = self.includes.keys().cloned().collect();
+        for includes in self.includes.values() {
+            files.extend(includes.iter().cloned());
+        }
+
+        files.into_iter().collect()
+    }
+
+    /// Entry shaders which have to be rebuilt after a change of `files`.
+    pub fn dependents<P: AsRef<Path>>(&self, files: &[P]) -> Vec<PathBuf> {
+        self.includes
+            .iter()
+            .filter(|(entry, includes)| {
+                files
+                    .iter()
+                    .any(|f| f.as_ref() == entry.as_path() || includes.contains(f.as_ref()))
+            })
+            .map(|(entry, _)| entry.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shader(name: &str) -> PathBuf {
+        Path::new("assets/shaders").join(name)
+    }
+
+    fn sorted_dependents(graph: &IncludeGraph, file: &str) -> Vec<PathBuf> {
+        let mut entries = graph.dependents(&[shader(file)]);
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn nested_include_marks_the_entry() {
+        let mut graph = IncludeGraph::new();
+        // raymarch.glsl includes utils.glsl, the callback reports both for main.glsl
+        graph.set_includes(
+            &shader("main.glsl"),
+            [shader("raymarch.glsl"), shader("utils.glsl")],
+        );
+
+        assert_eq!(
+            sorted_dependents(&graph, "utils.glsl"),
+            [shader("main.glsl")]
+        );
+        assert_eq!(
+            sorted_dependents(&graph, "main.glsl"),
+            [shader("main.glsl")]
+        );
+        assert!(sorted_dependents(&graph, "quad.frag").is_empty());
+    }
+
+    #[test]
+    fn shared_include_marks_every_entry() {
+        let mut graph = IncludeGraph::new();
+        graph.set_includes(
+            &shader("main.glsl"),
+            [shader("sdf.glsl"), shader("utils.glsl")],
+        );
+        graph.set_includes(&shader("pathtrace.glsl"), [shader("sdf.glsl")]);
+
+        assert_eq!(
+            sorted_dependents(&graph, "sdf.glsl"),
+            [shader("main.glsl"), shader("pathtrace.glsl")]
+        );
+        assert_eq!(
+            sorted_dependents(&graph, "utils.glsl"),
+            [shader("main.glsl")]
+        );
+        assert_eq!(
+            graph.files(),
+            [
+                shader("main.glsl"),
+                shader("pathtrace.glsl"),
+                shader("sdf.glsl"),
+                shader("utils.glsl")
+            ]
+        );
+    }
+
+    #[test]
+    fn removed_include_is_dropped() {
+        let mut graph = IncludeGraph::new();
+        graph.set_includes(
+            &shader("main.glsl"),
+            [shader("sdf.glsl"), shader("utils.glsl")],
+        );
+        graph.set_includes(&shader("main.glsl"), [shader("sdf.glsl")]);
+
+        assert!(sorted_dependents(&graph, "utils.glsl").is_empty());
+        assert_eq!(graph.files(), [shader("main.glsl"), shader("sdf.glsl")]);
+    }
+}
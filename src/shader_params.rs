@@ -0,0 +1,517 @@
+//! Parameters declared by the shaders in the `Params` uniform block, tweaked from the ui
+//! without touching the Rust code. The widget of each member is described by an
+//! annotation comment on its declaration:
+//!
+//! ```glsl
+//! layout(set=0, binding=6) uniform Params {
+//!     float u_smooth;   // @slider(0, 1) @default(0.5)
+//!     vec3 u_tint;      // @color @default(1, 1, 1)
+//!     int u_iterations; // @slider(1, 16)
+//!     bool u_fog;       // @checkbox
+//! };
+//! ```
+//!
+//! The offsets of the members come from the reflection of the compiled shader, and the
+//! values are saved per shader file in `assets::params_dir`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::assets;
+use crate::reflection::{Resource, Scalar, ShaderReflection, Type};
+
+/// Binding of the `Params` block in set 0.
+pub const PARAMS_BINDING: u32 = 6;
+/// Size of the parameter buffer, larger blocks are rejected.
+pub const PARAMS_MAX_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Widget {
+    /// `@slider(min, max)`
+    Slider { min: f32, max: f32 },
+    /// `@drag(speed)`, or a member without annotation.
+    Drag { speed: f32 },
+    /// `@color`, for `vec3` and `vec4`.
+    Color,
+    /// `@checkbox`, for `bool` and integers.
+    Checkbox,
+}
+
+impl Default for Widget {
+    fn default() -> Self {
+        Widget::Drag { speed: 0.01 }
+    }
+}
+
+/// Widget and initial value parsed from the comment of a declaration.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Annotation {
+    pub widget: Widget,
+    pub default: Option<[f32; 4]>,
+}
+
+impl Annotation {
+    /// Parse the annotations in `comment`, `None` when it has none.
+    fn parse(comment: &str) -> Result<Option<Self>, String> {
+        let mut annotation = None;
+
+        for item in comment.split('@').skip(1) {
+            let item = item.trim();
+            let (name, args) = match item.find('(') {
+                Some(open) => {
+                    let close = item
+                        .find(')')
+                        .ok_or_else(|| format!("missing `)` in `@{}`", item))?;
+                    let args = item[open + 1..close]
+                        .split(',')
+                        .map(|a| a.trim().parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| format!("invalid arguments in `@{}`", item))?;
+                    (&item[..open], args)
+                }
+                None => (item.split_whitespace().next().unwrap_or(""), Vec::new()),
+            };
+
+            let annotation = annotation.get_or_insert_with(Annotation::default);
+            match (name.trim(), args.as_slice()) {
+                ("slider", [min, max]) => {
+                    annotation.widget = Widget::Slider {
+                        min: *min,
+                        max: *max,
+                    }
+                }
+                ("drag", [speed]) => annotation.widget = Widget::Drag { speed: *speed },
+                ("drag", []) => annotation.widget = Widget::default(),
+                ("color", []) => annotation.widget = Widget::Color,
+                ("checkbox", []) => annotation.widget = Widget::Checkbox,
+                ("default", values) if (1..=4).contains(&values.len()) => {
+                    let mut default = [0.0; 4];
+                    default[..values.len()].copy_from_slice(values);
+                    annotation.default = Some(default);
+                }
+                (name, _) => return Err(format!("unknown annotation `@{}`", name)),
+            }
+        }
+
+        Ok(annotation)
+    }
+}
+
+/// Annotations of the declarations in `source`, by declared name. Invalid annotations
+/// are reported and ignored.
+pub fn parse_annotations(file: &str, source: &str) -> HashMap<String, Annotation> {
+    let mut annotations = HashMap::new();
+
+    for (line, text) in source.lines().enumerate() {
+        let (code, comment) = match text.split_once("//") {
+            Some(split) => split,
+            None => continue,
+        };
+        // the name is the last identifier of a declaration such as `float u_smooth;`
+        let name = match code.trim().strip_suffix(';') {
+            Some(declaration) => declaration.split_whitespace().last().unwrap_or(""),
+            None => continue,
+        };
+
+        match Annotation::parse(comment) {
+            Ok(Some(annotation)) => {
+                annotations.insert(name.to_string(), annotation);
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("{}:{}: {}", file, line + 1, e),
+        }
+    }
+
+    annotations
+}
+
+/// A member of the `Params` block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShaderParam {
+    pub name: String,
+    pub scalar: Scalar,
+    /// 1 for scalars, up to 4 for vectors.
+    pub components: u32,
+    /// Byte offset in the block.
+    pub offset: u32,
+    pub widget: Widget,
+    pub default: [f32; 4],
+    /// Integers and booleans are stored as floats and converted when written.
+    pub value: [f32; 4],
+}
+
+impl ShaderParam {
+    fn write(&self, bytes: &mut [u8]) {
+        for i in 0..self.components as usize {
+            let value = self.value[i];
+            let word = match self.scalar {
+                Scalar::Float => value.to_le_bytes(),
+                Scalar::Int => (value.round() as i32).to_le_bytes(),
+                Scalar::Bool | Scalar::Uint => (value.round().max(0.0) as u32).to_le_bytes(),
+            };
+            let offset = self.offset as usize + 4 * i;
+            bytes[offset..offset + 4].copy_from_slice(&word);
+        }
+    }
+}
+
+/// Members of the `Params` block declared by a shader, with their offsets and widgets.
+/// Shaders without the block have no parameter.
+pub fn reflect_params(
+    reflection: &ShaderReflection,
+    annotations: &HashMap<String, Annotation>,
+) -> Result<Vec<ShaderParam>, String> {
+    let block = match reflection.binding(0, PARAMS_BINDING).map(|b| &b.resource) {
+        Some(Resource::UniformBuffer(block)) => block,
+        Some(_) => {
+            return Err(format!(
+                "binding {} is reserved for the `Params` uniform block",
+                PARAMS_BINDING
+            ))
+        }
+        None => return Ok(Vec::new()),
+    };
+
+    block
+        .members
+        .iter()
+        .map(|member| {
+            let (scalar, components) = match member.ty {
+                Type::Scalar(s) => (s, 1),
+                Type::Vector(s, n) => (s, n),
+                _ => {
+                    return Err(format!(
+                        "parameter `{}` is not a scalar or a vector",
+                        member.name
+                    ))
+                }
+            };
+            let offset = member.offset.unwrap_or(0);
+            if offset + 4 * components > PARAMS_MAX_SIZE {
+                return Err(format!(
+                    "parameter `{}` does not fit in the {} bytes of the `Params` block",
+                    member.name, PARAMS_MAX_SIZE
+                ));
+            }
+
+            let annotation = annotations.get(&member.name).copied().unwrap_or_default();
+            let default = annotation.default.unwrap_or(match annotation.widget {
+                Widget::Slider { min, .. } => [min; 4],
+                Widget::Color => [1.0; 4],
+                _ => [0.0; 4],
+            });
+
+            Ok(ShaderParam {
+                name: member.name.clone(),
+                scalar,
+                components,
+                offset,
+                widget: annotation.widget,
+                default,
+                value: default,
+            })
+        })
+        .collect()
+}
+
+/// Parameters of one shader file and their saved values.
+#[derive(Debug, Clone)]
+pub struct ShaderParams {
+    path: PathBuf,
+    params: Vec<ShaderParam>,
+    // values by name, including the ones of parameters which are not declared anymore,
+    // they come back when the declaration is restored
+    saved: BTreeMap<String, Vec<f32>>,
+}
+
+impl ShaderParams {
+    /// Parameters of `shader`, a file name such as `main.glsl`, with the values saved
+    /// by a previous session.
+    pub fn new(shader: &str) -> Self {
+        let path = assets::params_dir().join(format!("{}.ron", shader));
+        let saved = match fs::read_to_string(&path) {
+            Ok(ron) => ron::from_str(&ron).unwrap_or_else(|e| {
+                eprintln!("invalid parameters in {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+
+        Self {
+            path,
+            params: Vec::new(),
+            saved,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn params(&self) -> &[ShaderParam] {
+        &self.params
+    }
+
+    pub fn params_mut(&mut self) -> &mut [ShaderParam] {
+        &mut self.params
+    }
+
+    /// Use the layout of a new build of the shader, parameters keep their value when
+    /// their name and size did not change.
+    pub fn set_layout(&mut self, params: Vec<ShaderParam>) {
+        self.store_values();
+        self.params = params;
+        for param in &mut self.params {
+            match self.saved.get(&param.name) {
+                Some(value) if value.len() == param.components as usize => {
+                    param.value[..value.len()].copy_from_slice(value)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn store_values(&mut self) {
+        for param in &self.params {
+            self.saved.insert(
+                param.name.clone(),
+                param.value[..param.components as usize].to_vec(),
+            );
+        }
+    }
+
+    pub fn reset(&mut self) {
+        for param in &mut self.params {
+            param.value = param.default;
+        }
+    }
+
+    /// Contents of the parameter buffer.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; PARAMS_MAX_SIZE as usize];
+        for param in &self.params {
+            param.write(&mut bytes);
+        }
+
+        bytes
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        self.store_values();
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let ron = ron::ser::to_string_pretty(&self.saved, ron::ser::PrettyConfig::new())
+            .map_err(io::Error::other)?;
+        fs::write(&self.path, ron)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflection::{self, Binding, Member, Struct};
+
+    const SOURCE: &str = "
+        layout(set=0, binding=6) uniform Params {
+            float u_smooth; // @slider(0, 2) @default(1)
+            float u_scale;
+            vec3 u_tint;    // @color
+        };
+    ";
+
+    fn params_block(members: &[(&str, u32, Type)]) -> ShaderReflection {
+        let members = members
+            .iter()
+            .map(|(name, offset, ty)| Member {
+                name: name.to_string(),
+                offset: Some(*offset),
+                ty: ty.clone(),
+            })
+            .collect();
+
+        ShaderReflection {
+            bindings: vec![Binding {
+                set: 0,
+                binding: PARAMS_BINDING,
+                name: String::new(),
+                resource: Resource::UniformBuffer(Struct {
+                    name: "Params".to_string(),
+                    members,
+                }),
+            }],
+        }
+    }
+
+    /// The block of `SOURCE` with the std140 offsets, the vec3 is aligned on 16 bytes.
+    fn params() -> Vec<ShaderParam> {
+        let reflection = params_block(&[
+            ("u_smooth", 0, Type::Scalar(Scalar::Float)),
+            ("u_scale", 4, Type::Scalar(Scalar::Float)),
+            ("u_tint", 16, Type::Vector(Scalar::Float, 3)),
+        ]);
+        reflect_params(&reflection, &parse_annotations("params.glsl", SOURCE)).unwrap()
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks(4)
+            .map(|w| f32::from_le_bytes(w.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(
+            Annotation::parse(" @slider(0, 2) @default(1)"),
+            Ok(Some(Annotation {
+                widget: Widget::Slider { min: 0.0, max: 2.0 },
+                default: Some([1.0, 0.0, 0.0, 0.0]),
+            }))
+        );
+        assert_eq!(
+            Annotation::parse(" @color"),
+            Ok(Some(Annotation {
+                widget: Widget::Color,
+                default: None,
+            }))
+        );
+        assert_eq!(
+            Annotation::parse("@drag(0.5) @default(1, 2, 3)"),
+            Ok(Some(Annotation {
+                widget: Widget::Drag { speed: 0.5 },
+                default: Some([1.0, 2.0, 3.0, 0.0]),
+            }))
+        );
+        assert_eq!(Annotation::parse(" a plain comment"), Ok(None));
+    }
+
+    #[test]
+    fn malformed_annotations() {
+        for comment in [
+            "@slider(0, 2",
+            "@slider(zero, 2)",
+            "@slider(1)",
+            "@color(1)",
+            "@default()",
+            "@default(1, 2, 3, 4, 5)",
+            "@spinner",
+        ] {
+            assert!(Annotation::parse(comment).is_err(), "{}", comment);
+        }
+
+        // the other declarations keep their annotations
+        let annotations = parse_annotations(
+            "params.glsl",
+            "float a; // @slider(0\nfloat b; // @checkbox\n",
+        );
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations["b"].widget, Widget::Checkbox);
+    }
+
+    #[test]
+    fn std140_bytes() {
+        let params = params();
+        assert_eq!(
+            params
+                .iter()
+                .map(|p| (p.offset, p.components))
+                .collect::<Vec<_>>(),
+            [(0, 1), (4, 1), (16, 3)]
+        );
+        // defaults from the annotations, a slider or a color
+        assert_eq!(params[0].value[0], 1.0);
+        assert_eq!(params[1].value[0], 0.0);
+        assert_eq!(params[2].value, [1.0; 4]);
+
+        let mut shader_params = ShaderParams::new("params.glsl");
+        shader_params.set_layout(params);
+        shader_params.params_mut()[1].value[0] = 0.25;
+        shader_params.params_mut()[2].value = [0.1, 0.2, 0.3, 0.4];
+
+        let bytes = shader_params.to_bytes();
+        assert_eq!(bytes.len(), PARAMS_MAX_SIZE as usize);
+        // the padding before the vec3 and its fourth component stay zeroed
+        assert_eq!(
+            floats(&bytes[..32]),
+            [1.0, 0.25, 0.0, 0.0, 0.1, 0.2, 0.3, 0.0]
+        );
+        assert!(bytes[32..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn integer_bytes() {
+        let reflection = params_block(&[
+            ("u_iterations", 0, Type::Scalar(Scalar::Int)),
+            ("u_fog", 4, Type::Scalar(Scalar::Bool)),
+        ]);
+        let mut shader_params = ShaderParams::new("params.glsl");
+        shader_params.set_layout(reflect_params(&reflection, &HashMap::new()).unwrap());
+        shader_params.params_mut()[0].value[0] = -2.6;
+        shader_params.params_mut()[1].value[0] = 1.0;
+
+        let bytes = shader_params.to_bytes();
+        assert_eq!(i32::from_le_bytes(bytes[0..4].try_into().unwrap()), -3);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 1);
+    }
+
+    #[test]
+    fn values_survive_a_reload() {
+        let mut shader_params = ShaderParams::new("params.glsl");
+        shader_params.set_layout(params());
+        shader_params.params_mut()[0].value[0] = 1.5;
+        shader_params.params_mut()[1].value[0] = 0.25;
+        shader_params.params_mut()[2].value = [0.5, 0.5, 0.5, 1.0];
+
+        // `u_scale` was removed and `u_tint` became a vec4
+        let mut reloaded = params();
+        reloaded.remove(1);
+        reloaded[1].components = 4;
+        shader_params.set_layout(reloaded);
+        assert_eq!(shader_params.params()[0].value[0], 1.5);
+        assert_eq!(shader_params.params()[1].value, [1.0; 4]);
+
+        // the removed parameter comes back with its value
+        shader_params.set_layout(params());
+        assert_eq!(shader_params.params()[0].value[0], 1.5);
+        assert_eq!(shader_params.params()[1].value[0], 0.25);
+        assert_eq!(shader_params.params()[2].value, [1.0; 4]);
+
+        shader_params.reset();
+        assert_eq!(shader_params.params()[0].value[0], 1.0);
+    }
+
+    #[test]
+    fn reserved_binding() {
+        let mut reflection = params_block(&[]);
+        reflection.bindings[0].resource = Resource::Sampler;
+        assert!(reflect_params(&reflection, &HashMap::new()).is_err());
+        assert_eq!(
+            reflect_params(&ShaderReflection::default(), &HashMap::new()),
+            Ok(Vec::new())
+        );
+    }
+
+    #[test]
+    fn compiled_std140_offsets() {
+        let source = format!(
+            "#version 450\nlayout(local_size_x = 1) in;\n{}\nvoid main() {{ float x = u_smooth + u_scale + u_tint.x; }}\n",
+            SOURCE
+        );
+        let spirv = shaderc::Compiler::new()
+            .unwrap()
+            .compile_into_spirv(
+                &source,
+                shaderc::ShaderKind::Compute,
+                "params.comp",
+                "main",
+                None,
+            )
+            .unwrap();
+        let reflection = reflection::reflect(spirv.as_binary()).unwrap();
+        let annotations = parse_annotations("params.comp", &source);
+        assert_eq!(reflect_params(&reflection, &annotations), Ok(params()));
+    }
+}
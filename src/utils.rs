@@ -1,19 +1,49 @@
-use crate::camera::Camera;
-use wgpu_sandbox::prelude::{
-    wgpu::{self, util::DeviceExt},
-    Gpu,
+use crate::{
+    assets,
+    camera::Camera,
+    reflection::{field, Field},
+    scene::{LightKind, SceneLight, SceneMaterial},
 };
+use glam::Vec3;
+use wgpu_sandbox::prelude::wgpu::{self, util::DeviceExt};
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, serde::Serialize)]
 pub struct ComputeUniforms {
     pub camera: Camera,
     pub time: f32,
+    // index of the accumulated sample, 0 restarts the average
+    // integers are `int` in the shader
+    pub sample_index: i32,
+    pub accumulate: i32,
+    pub max_bounces: i32,
+    pub shading_mode: i32,
 }
 
 impl ComputeUniforms {
     pub fn new(camera: Camera, time: f32) -> Self {
-        Self { camera, time }
+        Self {
+            camera,
+            time,
+            sample_index: 0,
+            accumulate: 0,
+            max_bounces: 4,
+            shading_mode: 0,
+        }
+    }
+
+    /// Members of the `Uniforms` block of `raymarch.glsl`, in order.
+    pub fn fields() -> [Field; 8] {
+        [
+            field!(ComputeUniforms, camera.eye, "u_eye"),
+            field!(ComputeUniforms, camera.fov, "u_fov"),
+            field!(ComputeUniforms, camera.target, "u_target"),
+            field!(ComputeUniforms, time, "u_time"),
+            field!(ComputeUniforms, sample_index, "u_sample_index"),
+            field!(ComputeUniforms, accumulate, "u_accumulate"),
+            field!(ComputeUniforms, max_bounces, "u_max_bounces"),
+            field!(ComputeUniforms, shading_mode, "u_shading_mode"),
+        ]
     }
 
     pub fn build_buffer(&self, device: &wgpu::Device) -> wgpu::Buffer {
@@ -32,26 +62,204 @@ impl ComputeUniforms {
         self.camera = camera;
     }
 
-    pub fn update_buffer(&self, buffer: &wgpu::Buffer, gpu: &Gpu) {
-        gpu.queue
-            .write_buffer(buffer, 0, bytemuck::cast_slice(&[self.clone()]))
+    pub fn update_buffer(&self, buffer: &wgpu::Buffer, queue: &wgpu::Queue) {
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[self.clone()]))
     }
 }
 
 impl Default for ComputeUniforms {
     fn default() -> Self {
+        Self::new(Camera::default(), 0.0)
+    }
+}
+
+/// Mirror of the `Material` struct of `utils.glsl`, with the std430 layout of the
+/// materials storage buffer where every vec3 is aligned on 16 bytes.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Material {
+    pub diffuse: Vec3,
+    _pad0: f32,
+    pub ambient: Vec3,
+    _pad1: f32,
+    pub specular: Vec3,
+    pub specular_exponent: f32,
+    pub emission: Vec3,
+    _pad2: f32,
+}
+
+impl Material {
+    /// Capacity of the materials buffer, so the table can change without a new bind group.
+    pub const MAX_COUNT: usize = 256;
+
+    pub fn new(diffuse: Vec3, ambient: Vec3, specular: Vec3, specular_exponent: f32) -> Self {
         Self {
-            camera: Camera::default(),
-            time: 0.0,
+            diffuse,
+            _pad0: 0.0,
+            ambient,
+            _pad1: 0.0,
+            specular,
+            specular_exponent,
+            emission: Vec3::ZERO,
+            _pad2: 0.0,
         }
     }
+
+    /// Members of the `Material` struct of `utils.glsl`, in order.
+    pub fn fields() -> [Field; 5] {
+        [
+            field!(Material, diffuse, "diffuse"),
+            field!(Material, ambient, "ambient"),
+            field!(Material, specular, "specular"),
+            field!(Material, specular_exponent, "specular_exponent"),
+            field!(Material, emission, "emission"),
+        ]
+    }
+
+    pub fn build_buffer(materials: &[Material], device: &wgpu::Device) -> wgpu::Buffer {
+        let mut contents = vec![Material::default(); Self::MAX_COUNT];
+        contents[..materials.len()].copy_from_slice(materials);
+
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("materials"),
+            contents: bytemuck::cast_slice(&contents),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    pub fn update_buffer(materials: &[Material], buffer: &wgpu::Buffer, queue: &wgpu::Queue) {
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(materials))
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new(Vec3::ZERO, Vec3::ZERO, Vec3::ZERO, 1.0)
+    }
+}
+
+impl From<&SceneMaterial> for Material {
+    fn from(mat: &SceneMaterial) -> Self {
+        Self {
+            emission: mat.emission,
+            ..Self::new(
+                mat.diffuse,
+                mat.ambient,
+                mat.specular,
+                mat.specular_exponent,
+            )
+        }
+    }
+}
+
+/// Mirror of the `Light` struct of `utils.glsl`, with the std430 layout of the lights
+/// storage buffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Light {
+    pub position: Vec3,
+    pub kind: i32,
+    pub direction: Vec3,
+    pub intensity: f32,
+    pub color: Vec3,
+    pub shadow_k: f32,
+    pub spot_angle: f32,
+    pub spot_softness: f32,
+    _pad: [f32; 2],
+}
+
+impl Light {
+    /// Capacity of the lights buffer.
+    pub const MAX_COUNT: usize = 16;
+    /// Offset of the light array, after the light count aligned on 16 bytes.
+    pub const HEADER_SIZE: usize = 16;
+
+    /// Members of the `Light` struct of `utils.glsl`, in order.
+    pub fn fields() -> [Field; 8] {
+        [
+            field!(Light, position, "position"),
+            field!(Light, kind, "kind"),
+            field!(Light, direction, "direction"),
+            field!(Light, intensity, "intensity"),
+            field!(Light, color, "color"),
+            field!(Light, shadow_k, "shadow_k"),
+            field!(Light, spot_angle, "spot_angle"),
+            field!(Light, spot_softness, "spot_softness"),
+        ]
+    }
+
+    fn buffer_contents(lights: &[Light]) -> Vec<u8> {
+        let mut contents = vec![0; Self::HEADER_SIZE];
+        contents[..4].copy_from_slice(bytemuck::bytes_of(&(lights.len() as u32)));
+        contents.extend_from_slice(bytemuck::cast_slice(lights));
+        contents
+    }
+
+    pub fn build_buffer(lights: &[Light], device: &wgpu::Device) -> wgpu::Buffer {
+        let mut contents = Self::buffer_contents(lights);
+        contents.resize(
+            Self::HEADER_SIZE + Self::MAX_COUNT * std::mem::size_of::<Light>(),
+            0,
+        );
+
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lights"),
+            contents: &contents,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        })
+    }
+
+    pub fn update_buffer(lights: &[Light], buffer: &wgpu::Buffer, queue: &wgpu::Queue) {
+        queue.write_buffer(buffer, 0, &Self::buffer_contents(lights))
+    }
+}
+
+impl From<&SceneLight> for Light {
+    fn from(light: &SceneLight) -> Self {
+        Self {
+            position: light.position,
+            kind: match light.kind {
+                LightKind::Point => 0,
+                LightKind::Directional => 1,
+                LightKind::Spot => 2,
+            },
+            direction: light.direction.normalize_or_zero(),
+            intensity: light.intensity,
+            color: light.color,
+            shadow_k: light.shadow_k,
+            spot_angle: light.spot_angle,
+            spot_softness: light.spot_softness,
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+/// `Rgba8Unorm` texture written by the ray marching shader, it can be sampled by the
+/// display pass and copied back to the CPU.
+pub fn create_output_texture(device: &wgpu::Device, size: (u32, u32)) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("output_texture"),
+        size: wgpu::Extent3d {
+            width: size.0,
+            height: size.1,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8Unorm,
+        usage: wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
+    })
 }
 
-pub fn load_spirv_shader(path: &str, device: &wgpu::Device) -> std::io::Result<wgpu::ShaderModule> {
-    let data = std::fs::read(path)?;
-    let shader_source = wgpu::ShaderSource::SpirV(wgpu::util::make_spirv_raw(&data));
-    Ok(device.create_shader_module(&wgpu::ShaderModuleDescriptor {
-        label: Some(path),
+/// Module of the shader `name` compiled by `build.rs`, e.g. `quad.vert`.
+pub fn load_spirv_shader(name: &str, device: &wgpu::Device) -> wgpu::ShaderModule {
+    let shader_source =
+        wgpu::ShaderSource::SpirV(wgpu::util::make_spirv_raw(assets::compiled_shader(name)));
+    device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+        label: Some(name),
         source: shader_source,
-    }))
+    })
 }